@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::prelude::*;
+
+use thruster::{
+    AllocationMode, LinearProgramAllocator, MixedIntegerAllocator, PseudoInverseAllocator,
+    QuadraticAllocator, ResolvedEngine, ThrustAllocator, ThrusterSolverConfig,
+};
+
+/// Builds a ship the same way `make_random_ship` in the spaceship example does: a fan of
+/// engines around the hull, mirrored left to right.
+fn random_ship(rng: &mut impl Rng) -> Vec<ResolvedEngine> {
+    let mut engines = vec![];
+    let count = rng.gen_range(2..20);
+    let mut a = std::f32::consts::PI / 2.0;
+    let da = std::f32::consts::PI / count as f32;
+
+    for _ in 0..count {
+        let r = rng.gen_range(20.0..60.0) * (count as f32 / 10.0).max(1.0).powf(1.5);
+        let x = a.cos() * r;
+        let y = a.sin() * r;
+        a += da;
+        let engine_angle = if rng.gen::<f32>() < 0.5 {
+            rng.gen::<f32>() * std::f32::consts::PI * 2.0
+        } else {
+            std::f32::consts::PI / 2.0
+        };
+        engines.push(ResolvedEngine::new(
+            Vec2::new(x, y),
+            Vec2::new(engine_angle.cos(), engine_angle.sin()).normalize(),
+            1.0,
+            (Entity::new(0), 0),
+        ));
+    }
+    let mut reflected = engines.clone();
+    reflected.reverse();
+    for e in &mut reflected {
+        e.position.x *= -1.0;
+        e.thrust_vector.x *= -1.0;
+    }
+    engines.extend(reflected);
+    for (i, e) in engines.iter_mut().enumerate() {
+        e.id.1 = i;
+    }
+    engines
+}
+
+fn center_of_mass(engines: &[ResolvedEngine]) -> Vec2 {
+    engines.iter().map(|e| &e.position).sum::<Vec2>() / engines.len() as f32
+}
+
+/// The inputs a player produces from the spaceship example's keyboard controls.
+fn desires() -> Vec<(Vec2, f32)> {
+    let mut desires = vec![];
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for t in -1..=1 {
+                if x != 0 || y != 0 || t != 0 {
+                    desires.push((Vec2::new(x as f32, y as f32), t as f32));
+                }
+            }
+        }
+    }
+    desires
+}
+
+fn bench_allocator(c: &mut Criterion, name: &str, allocator: &dyn ThrustAllocator) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let ships: Vec<_> = (0..32).map(|_| random_ship(&mut rng)).collect();
+    let desires = desires();
+    let config = ThrusterSolverConfig::default();
+
+    c.bench_with_input(
+        BenchmarkId::new(name, "32 random ships"),
+        &ships,
+        |b, ships| {
+            b.iter(|| {
+                for engines in ships {
+                    let com = center_of_mass(engines);
+                    for (force, torque) in &desires {
+                        black_box(
+                            allocator
+                                .allocate(
+                                    engines,
+                                    com,
+                                    *force,
+                                    *torque,
+                                    AllocationMode::Blended,
+                                    &config,
+                                )
+                                .unwrap(),
+                        );
+                    }
+                }
+            })
+        },
+    );
+}
+
+fn allocators(c: &mut Criterion) {
+    bench_allocator(c, "linear_program", &LinearProgramAllocator);
+    bench_allocator(c, "pseudo_inverse", &PseudoInverseAllocator);
+    bench_allocator(c, "quadratic", &QuadraticAllocator);
+    bench_allocator(c, "mixed_integer", &MixedIntegerAllocator::default());
+}
+
+criterion_group!(benches, allocators);
+criterion_main!(benches);
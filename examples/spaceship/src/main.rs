@@ -6,7 +6,9 @@ use bevy_rapier2d::{
 };
 use rand::prelude::*;
 
-use thruster::{Engine, EngineEvent, EngineSet, Steering, ThrustScale, ThrusterPlugin};
+use thruster::{
+    Engine, EngineEvent, EngineSet, Steering, ThrustScale, ThrusterPlugin, VelocityHold,
+};
 
 fn main() {
     let mut app = App::build();
@@ -31,8 +33,22 @@ fn main() {
         .run();
 }
 
-fn player_controls(keyboard_input: Res<Input<KeyCode>>, mut steering_query: Query<&mut Steering>) {
-    if let Some(mut steering) = steering_query.iter_mut().next() {
+fn player_controls(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut steering_query: Query<(Entity, &mut Steering)>,
+) {
+    if let Some((entity, mut steering)) = steering_query.iter_mut().next() {
+        // Holding X brings the ship to a full stop.
+        if keyboard_input.just_pressed(KeyCode::X) {
+            commands.entity(entity).insert(VelocityHold::full_stop());
+        }
+        if keyboard_input.just_released(KeyCode::X) {
+            commands.entity(entity).remove::<VelocityHold>();
+        }
+        if keyboard_input.pressed(KeyCode::X) {
+            return;
+        }
         if keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up) {
             steering.desired_force.y = 1.0;
         } else if keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down) {
@@ -74,7 +90,6 @@ fn randomize_player_ship(
             let entity = commands
                 .spawn_bundle((
                     RigidBodyBuilder::new_dynamic()
-                        .linear_damping(0.9)
                         .angular_damping(0.9)
                         .position(pos),
                     Steering::default(),
@@ -116,6 +131,7 @@ fn make_random_ship(
             offset: Vec2::new(x, y),
             thrust_vector,
             max_thrust: 1.0,
+            ..Default::default()
         });
     }
     let mut reflected_engines = new_engines.clone();
@@ -316,7 +332,6 @@ fn setup(
     let entity = commands
         .spawn_bundle((
             RigidBodyBuilder::new_dynamic()
-                .linear_damping(0.9)
                 .angular_damping(0.9),
             Steering::default(),
         ))
@@ -427,12 +442,21 @@ fn maintain_engine_indicators(
                     }
                     if let Some(material) = materials.get_mut(handle) {
                         match event {
-                            EngineEvent::StartedFiring(_e, _i, amount) => {
+                            EngineEvent::StartedFiring(_e, _i, amount)
+                            | EngineEvent::ThrottleChanged(_e, _i, amount) => {
                                 material.color.set_a(*amount);
                             }
-                            EngineEvent::StoppedFiring(..) => {
+                            EngineEvent::StoppedFiring(..) | EngineEvent::Destroyed(..) => {
                                 material.color.set_a(0.0);
                             }
+                            EngineEvent::Gimbaled(..)
+                            | EngineEvent::OutOfFuel(..)
+                            | EngineEvent::Damaged(..)
+                            | EngineEvent::Overheated(..)
+                            | EngineEvent::CooledDown(..)
+                            | EngineEvent::BoostStarted(..)
+                            | EngineEvent::BoostStopped(..)
+                            | EngineEvent::BoostExhausted(..) => {}
                         }
                     }
                 }
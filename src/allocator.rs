@@ -0,0 +1,729 @@
+use bevy::math::Mat2;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{optimizer, Boost, Gimbal, Heat, SolveError, ThrottleMode, ThrusterSolverConfig};
+
+/// The widest gap between the fixed directions used to represent a gimbaled engine.
+const GIMBAL_SAMPLE_SPACING: f32 = std::f32::consts::PI / 8.0;
+
+/// An engine as seen by an allocator: resolved into its ship's body frame and scaled into
+/// rapier units.
+///
+/// A gimbaled engine is represented by several consecutive `ResolvedEngine`s which share an
+/// `id`, one for each of a fan of fixed deflections across its range. Their combined
+/// activation should not exceed one. Allocators which can't express that constraint may
+/// exceed it, in which case the engine fires at full thrust in the combined direction.
+#[derive(Copy, Clone, Debug)]
+pub struct ResolvedEngine {
+    /// Position of the engine relative to the ship's origin.
+    pub position: Vec2,
+    /// Unit vector in the direction the engine pushes the ship.
+    pub thrust_vector: Vec2,
+    pub max_thrust: f32,
+    /// The entity carrying the engine's `EngineSet` and the engine's index within it.
+    pub id: (Entity, usize),
+    pub gimbal: Option<Gimbal>,
+    /// How far `thrust_vector` is deflected from the engine's neutral direction, in radians.
+    pub deflection: f32,
+    /// See `Engine::min_throttle`. Only `MixedIntegerAllocator` plans around this, with other
+    /// allocators the engine snaps to off or to its minimum when it is fired.
+    pub min_throttle: f32,
+    /// See `Engine::discrete`. Only `MixedIntegerAllocator` plans around this, with other
+    /// allocators the engine snaps to off or to full thrust when it is fired.
+    pub discrete: bool,
+    pub spool_up_time: f32,
+    pub spool_down_time: f32,
+    pub fuel_consumption: f32,
+    pub heat: Option<Heat>,
+    /// The engine's boost, if `max_thrust` is currently its boosted rating.
+    pub boost: Option<Boost>,
+}
+
+impl ResolvedEngine {
+    /// A fixed engine with no other special behavior.
+    pub fn new(position: Vec2, thrust_vector: Vec2, max_thrust: f32, id: (Entity, usize)) -> Self {
+        Self {
+            position,
+            thrust_vector,
+            max_thrust,
+            id,
+            gimbal: None,
+            deflection: 0.0,
+            min_throttle: 0.0,
+            discrete: false,
+            spool_up_time: 0.0,
+            spool_down_time: 0.0,
+            fuel_consumption: 0.0,
+            heat: None,
+            boost: None,
+        }
+    }
+}
+
+/// What a single physical engine should do, after combining the activations of all the
+/// `ResolvedEngine`s that represent it.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct EngineCommand {
+    pub id: (Entity, usize),
+    pub position: Vec2,
+    /// Unit vector in the commanded direction of thrust, including any gimbal deflection.
+    pub thrust_vector: Vec2,
+    pub max_thrust: f32,
+    pub throttle: f32,
+    pub gimbal: Option<Gimbal>,
+    /// The commanded gimbal deflection, or `None` if the engine should hold its current one.
+    pub deflection: Option<f32>,
+    pub min_throttle: f32,
+    pub discrete: bool,
+    pub spool_up_time: f32,
+    pub spool_down_time: f32,
+    pub fuel_consumption: f32,
+    pub heat: Option<Heat>,
+    pub boost: Option<Boost>,
+}
+
+impl EngineCommand {
+    /// The engine's thrust as a fraction of its unboosted rating.
+    pub fn load(&self) -> f32 {
+        self.throttle * self.boost.map_or(1.0, |boost| boost.thrust_multiplier)
+    }
+
+    /// How far into its boost the engine is, from zero at or below its unboosted rating to one
+    /// at full boost.
+    pub fn boost_usage(&self) -> f32 {
+        match self.boost {
+            Some(boost) if boost.thrust_multiplier > 1.0 => {
+                ((self.load() - 1.0) / (boost.thrust_multiplier - 1.0)).clamp(0.0, 1.0)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// The deflections used to represent an engine to the allocators. Fixed engines get a single
+/// undeflected direction.
+pub(crate) fn gimbal_samples(gimbal: Option<Gimbal>) -> Vec<f32> {
+    match gimbal {
+        Some(gimbal) if gimbal.range > 0.0 => {
+            let steps = (gimbal.range / GIMBAL_SAMPLE_SPACING).ceil() as usize * 2;
+            (0..=steps)
+                .map(|k| -gimbal.range + 2.0 * gimbal.range * k as f32 / steps as f32)
+                .collect()
+        }
+        _ => vec![0.0],
+    }
+}
+
+/// Splits `engines` into the runs which share an `id`, one for each physical engine.
+pub(crate) fn engine_groups(engines: &[ResolvedEngine]) -> impl Iterator<Item = &[ResolvedEngine]> {
+    let mut rest = engines;
+    std::iter::from_fn(move || {
+        let id = rest.first()?.id;
+        let len = rest.iter().take_while(|engine| engine.id == id).count();
+        let (group, tail) = rest.split_at(len);
+        rest = tail;
+        Some(group)
+    })
+}
+
+/// Rounds a throttle to one the engine can actually run at.
+fn snap_throttle(throttle: f32, min_throttle: f32, discrete: bool) -> f32 {
+    let min_throttle = if discrete { 1.0 } else { min_throttle };
+    if throttle <= 0.0 || throttle >= min_throttle {
+        throttle
+    } else if throttle >= min_throttle / 2.0 {
+        min_throttle
+    } else {
+        0.0
+    }
+}
+
+/// Folds an allocation back into one command per physical engine, applying the throttle mode.
+/// Engines missing from `firing` are commanded off.
+pub(crate) fn engine_commands(
+    engines: &[ResolvedEngine],
+    firing: &[f32],
+    throttle_mode: ThrottleMode,
+) -> Vec<EngineCommand> {
+    let mut commands: Vec<EngineCommand> = Vec::with_capacity(engines.len());
+    let mut resultants: Vec<Vec2> = Vec::with_capacity(engines.len());
+    for (i, engine) in engines.iter().enumerate() {
+        let firing = firing.get(i).copied().unwrap_or(0.0);
+        let contribution = engine.thrust_vector * firing.max(0.0);
+        match commands.last() {
+            Some(command) if command.id == engine.id => {
+                *resultants.last_mut().unwrap() += contribution;
+            }
+            _ => {
+                commands.push(EngineCommand {
+                    id: engine.id,
+                    position: engine.position,
+                    thrust_vector: Mat2::from_angle(-engine.deflection) * engine.thrust_vector,
+                    max_thrust: engine.max_thrust,
+                    throttle: 0.0,
+                    gimbal: engine.gimbal,
+                    deflection: None,
+                    min_throttle: engine.min_throttle,
+                    discrete: engine.discrete,
+                    spool_up_time: engine.spool_up_time,
+                    spool_down_time: engine.spool_down_time,
+                    fuel_consumption: engine.fuel_consumption,
+                    heat: engine.heat,
+                    boost: engine.boost,
+                });
+                resultants.push(contribution);
+            }
+        }
+    }
+    for (command, resultant) in commands.iter_mut().zip(resultants) {
+        command.throttle = snap_throttle(
+            throttle_mode.throttle(resultant.length()),
+            command.min_throttle,
+            command.discrete,
+        );
+        if command.throttle > 0.0 {
+            if let Some(gimbal) = command.gimbal {
+                let deflection = command
+                    .thrust_vector
+                    .angle_between(resultant)
+                    .clamp(-gimbal.range, gimbal.range);
+                command.thrust_vector = Mat2::from_angle(deflection) * command.thrust_vector;
+                command.deflection = Some(deflection);
+            }
+        }
+    }
+    commands
+}
+
+/// How an allocator should trade off force error against torque error when the ship can't
+/// achieve both.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub enum AllocationMode {
+    /// Minimize a weighted sum of the errors, using the weights in `ThrusterSolverConfig`.
+    #[default]
+    Blended,
+    /// Get as close as possible to the desired torque, then get as close as possible to the
+    /// desired force without giving up any torque accuracy.
+    TorqueFirst,
+    /// Get as close as possible to the desired force, then get as close as possible to the
+    /// desired torque without giving up any force accuracy.
+    ForceFirst,
+    /// Get as close as possible to the desired force and torque, then burn as little
+    /// propellant as possible without giving up any accuracy. Engines are costed by
+    /// `Engine::fuel_consumption`, so efficient engines are preferred over thirsty ones.
+    FuelOptimal,
+}
+
+impl AllocationMode {
+    /// Approximates the mode for allocators which can only minimize a weighted sum of errors,
+    /// by making the prioritized error overwhelmingly expensive. For `FuelOptimal` both errors
+    /// are made expensive, leaving fuel as the tie breaker.
+    pub fn weight_config(&self, config: &ThrusterSolverConfig) -> ThrusterSolverConfig {
+        const PRIORITY_WEIGHT: f32 = 1000.0;
+        let mut config = *config;
+        match self {
+            AllocationMode::Blended => (),
+            AllocationMode::TorqueFirst => config.torque_weight *= PRIORITY_WEIGHT,
+            AllocationMode::ForceFirst => config.force_weight *= PRIORITY_WEIGHT,
+            AllocationMode::FuelOptimal => {
+                config.torque_weight *= PRIORITY_WEIGHT;
+                config.force_weight *= PRIORITY_WEIGHT;
+            }
+        }
+        config
+    }
+}
+
+/// Maps a ship's desired force and torque onto activations of its engines.
+///
+/// `desired_force` and `desired_torque` are normalized the same way as the fields on
+/// `Steering`, so a desired force of length one asks for as much thrust as the ship has.
+pub trait ThrustAllocator: Send + Sync + 'static {
+    /// Returns one activation in `[0, 1]` per engine, in the same order as `engines`.
+    fn allocate(
+        &self,
+        engines: &[ResolvedEngine],
+        center_of_mass: Vec2,
+        desired_force: Vec2,
+        desired_torque: f32,
+        mode: AllocationMode,
+        config: &ThrusterSolverConfig,
+    ) -> Result<Vec<f32>, SolveError>;
+}
+
+/// The default allocator. Solves a linear program which minimizes the weighted error in
+/// force and torque plus a small cost for fuel. Prioritized modes are solved exactly, as a
+/// second linear program constrained by the result of the first.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LinearProgramAllocator;
+
+impl ThrustAllocator for LinearProgramAllocator {
+    fn allocate(
+        &self,
+        engines: &[ResolvedEngine],
+        center_of_mass: Vec2,
+        desired_force: Vec2,
+        desired_torque: f32,
+        mode: AllocationMode,
+        config: &ThrusterSolverConfig,
+    ) -> Result<Vec<f32>, SolveError> {
+        optimizer::calculate_firing(
+            engines,
+            center_of_mass,
+            desired_force,
+            desired_torque,
+            mode,
+            config,
+        )
+    }
+}
+
+/// Returns the ship's total thrust and the most torque it can produce in each direction,
+/// which is what desires on `Steering` are normalized against. The fan of directions
+/// representing a gimbaled engine only counts once, using whichever direction is best.
+pub(crate) fn thrust_totals(engines: &[ResolvedEngine], center_of_mass: Vec2) -> (f32, f32, f32) {
+    let mut total_thrust = 0.0;
+    let mut total_positive_torque = 0.0;
+    let mut total_negative_torque = 0.0;
+    for group in engine_groups(engines) {
+        total_thrust += group[0].max_thrust;
+        let torques = group.iter().map(|engine| {
+            (engine.position - center_of_mass)
+                .perp_dot(engine.thrust_vector.normalize() * engine.max_thrust)
+        });
+        let (positive, negative) =
+            torques.fold((0.0f32, 0.0f32), |(p, n), t| (p.max(t), n.max(-t)));
+        total_positive_torque += positive;
+        total_negative_torque += negative;
+    }
+    (total_thrust, total_positive_torque, total_negative_torque)
+}
+
+/// Returns the force and torque each engine produces at full activation, and the desire
+/// scaled into the same units, using the same normalization as `LinearProgramAllocator`.
+pub(crate) fn effectiveness(
+    engines: &[ResolvedEngine],
+    center_of_mass: Vec2,
+    desired_force: Vec2,
+    desired_torque: f32,
+) -> (Vec<[f64; 3]>, [f64; 3]) {
+    let (total_thrust, total_positive_torque, total_negative_torque) =
+        thrust_totals(engines, center_of_mass);
+    let effects = engines
+        .iter()
+        .map(|engine| {
+            let force = engine.thrust_vector.normalize() * engine.max_thrust;
+            let torque = (engine.position - center_of_mass).perp_dot(force);
+            [force.x as f64, force.y as f64, torque as f64]
+        })
+        .collect();
+    let desired_force = desired_force * total_thrust;
+    let desired_torque = if desired_torque > 0.0 {
+        desired_torque * total_positive_torque
+    } else {
+        desired_torque * total_negative_torque
+    };
+    (
+        effects,
+        [
+            desired_force.x as f64,
+            desired_force.y as f64,
+            desired_torque as f64,
+        ],
+    )
+}
+
+/// Like `effectiveness` but with each row normalized and then scaled by the solver weights,
+/// so force and torque errors are on comparable scales. Without the normalization the torque
+/// row dominates and 3x3 solves against the effectiveness matrix are badly conditioned.
+pub(crate) fn weighted_effectiveness(
+    engines: &[ResolvedEngine],
+    center_of_mass: Vec2,
+    desired_force: Vec2,
+    desired_torque: f32,
+    config: &ThrusterSolverConfig,
+) -> (Vec<[f64; 3]>, [f64; 3]) {
+    let (effects, desire) = effectiveness(engines, center_of_mass, desired_force, desired_torque);
+    let weights = [
+        config.force_weight as f64,
+        config.force_weight as f64,
+        config.torque_weight as f64,
+    ];
+    let mut row_scale = [0.0f64; 3];
+    for effect in &effects {
+        for (scale, value) in row_scale.iter_mut().zip(effect) {
+            *scale += value * value;
+        }
+    }
+    for (scale, weight) in row_scale.iter_mut().zip(&weights) {
+        *scale = if *scale > 0.0 {
+            weight / scale.sqrt()
+        } else {
+            0.0
+        };
+    }
+    let scale = |v: &[f64; 3]| {
+        [
+            v[0] * row_scale[0],
+            v[1] * row_scale[1],
+            v[2] * row_scale[2],
+        ]
+    };
+    (effects.iter().map(scale).collect(), scale(&desire))
+}
+
+/// How much each engine's activation costs relative to the others, for the allocators which
+/// penalize the square of it. Every engine costs the same except in
+/// `AllocationMode::FuelOptimal`, where each is costed by its `fuel_consumption` relative to
+/// the ship's average.
+pub(crate) fn fuel_costs(engines: &[ResolvedEngine], mode: AllocationMode) -> Vec<f64> {
+    /// Keeps engines which burn nothing from being infinitely cheap.
+    const MIN_FUEL_COST: f64 = 1e-3;
+    let total: f64 = engines
+        .iter()
+        .map(|engine| engine.fuel_consumption as f64)
+        .sum();
+    if mode != AllocationMode::FuelOptimal || total <= 0.0 {
+        return vec![1.0; engines.len()];
+    }
+    let mean = total / engines.len() as f64;
+    engines
+        .iter()
+        .map(|engine| (engine.fuel_consumption as f64 / mean).max(MIN_FUEL_COST))
+        .collect()
+}
+
+/// Sums the outer products of `effects` with themselves, each divided by its engine's cost,
+/// and adds `damping` to the diagonal.
+pub(crate) fn gram<'a>(
+    effects: impl Iterator<Item = (&'a [f64; 3], &'a f64)>,
+    damping: f64,
+) -> [[f64; 3]; 3] {
+    let mut gram = [[0.0f64; 3]; 3];
+    for (e, cost) in effects {
+        for (r, row) in gram.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value += e[r] * e[c] / cost;
+            }
+        }
+    }
+    for (i, row) in gram.iter_mut().enumerate() {
+        row[i] += damping;
+    }
+    gram
+}
+
+/// Solves `a * x = b` by Gaussian elimination with partial pivoting.
+pub(crate) fn solve3(mut a: [[f64; 3]; 3], mut b: [f64; 3]) -> Option<[f64; 3]> {
+    for col in 0..3 {
+        let pivot = (col..3).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < f64::EPSILON {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in col + 1..3 {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, pivot_value) in a[row].iter_mut().zip(&pivot_row).skip(col) {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; 3];
+    for row in (0..3).rev() {
+        let sum: f64 = (row + 1..3).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    if x.iter().all(|v| v.is_finite()) {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+/// Rejects inputs that no allocator can do anything sensible with, so individual
+/// allocators don't have to guard against NaNs themselves.
+pub(crate) fn validate_inputs(
+    engines: &[ResolvedEngine],
+    center_of_mass: Vec2,
+    desired_force: Vec2,
+    desired_torque: f32,
+) -> Result<(), SolveError> {
+    for engine in engines {
+        let gimbal_valid = engine.gimbal.map_or(true, |gimbal| {
+            (0.0..=std::f32::consts::PI).contains(&gimbal.range) && gimbal.slew_rate > 0.0
+        });
+        if !engine.position.is_finite()
+            || !engine.thrust_vector.is_finite()
+            || engine.thrust_vector.length_squared() == 0.0
+            || !engine.max_thrust.is_finite()
+            || engine.max_thrust < 0.0
+            || !gimbal_valid
+            || !(0.0..=1.0).contains(&engine.min_throttle)
+            || !(engine.spool_up_time >= 0.0 && engine.spool_down_time >= 0.0)
+        {
+            return Err(SolveError::InvalidEngine(engine.id.0, engine.id.1));
+        }
+    }
+    if !center_of_mass.is_finite() || !desired_force.is_finite() || !desired_torque.is_finite() {
+        return Err(SolveError::InvalidDesire);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+
+    /// A ship centered on the origin with two main engines, a weaker retro engine and four
+    /// RCS thrusters, one pushing each way at the nose and at the tail.
+    pub fn ship() -> Vec<ResolvedEngine> {
+        let engines = [
+            (Vec2::new(-1.0, 0.0), Vec2::new(0.0, 1.0), 1.0),
+            (Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0), 1.0),
+            (Vec2::new(0.0, 0.0), Vec2::new(0.0, -1.0), 0.5),
+            (Vec2::new(0.0, 1.0), Vec2::new(1.0, 0.0), 0.5),
+            (Vec2::new(0.0, 1.0), Vec2::new(-1.0, 0.0), 0.5),
+            (Vec2::new(0.0, -1.0), Vec2::new(1.0, 0.0), 0.5),
+            (Vec2::new(0.0, -1.0), Vec2::new(-1.0, 0.0), 0.5),
+        ];
+        engines
+            .iter()
+            .enumerate()
+            .map(|(i, (position, thrust_vector, max_thrust))| {
+                ResolvedEngine::new(*position, *thrust_vector, *max_thrust, (Entity::new(0), i))
+            })
+            .collect()
+    }
+
+    /// Allocates for a ship centered on the origin in `Blended` mode with the default config.
+    pub fn allocate(
+        allocator: &dyn ThrustAllocator,
+        engines: &[ResolvedEngine],
+        force: Vec2,
+        torque: f32,
+    ) -> Vec<f32> {
+        allocator
+            .allocate(
+                engines,
+                Vec2::ZERO,
+                force,
+                torque,
+                AllocationMode::Blended,
+                &ThrusterSolverConfig::default(),
+            )
+            .unwrap()
+    }
+
+    /// The force and torque a normalized desire asks for, in physical units.
+    pub fn desired(engines: &[ResolvedEngine], force: Vec2, torque: f32) -> (Vec2, f32) {
+        let (_, desire) = effectiveness(engines, Vec2::ZERO, force, torque);
+        (
+            Vec2::new(desire[0] as f32, desire[1] as f32),
+            desire[2] as f32,
+        )
+    }
+
+    /// The force and torque an allocation produces, in physical units.
+    pub fn achieved(engines: &[ResolvedEngine], activations: &[f32]) -> (Vec2, f32) {
+        assert_eq!(activations.len(), engines.len());
+        let (effects, _) = effectiveness(engines, Vec2::ZERO, Vec2::ZERO, 0.0);
+        let mut total = (Vec2::ZERO, 0.0);
+        for (effect, activation) in effects.iter().zip(activations) {
+            assert!(
+                (0.0..=1.0).contains(activation),
+                "activation {} out of range",
+                activation
+            );
+            total.0 += Vec2::new(effect[0] as f32, effect[1] as f32) * *activation;
+            total.1 += effect[2] as f32 * activation;
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::*;
+    use super::*;
+    use crate::{MixedIntegerAllocator, PseudoInverseAllocator, QuadraticAllocator};
+
+    fn assert_close(name: &str, a: (Vec2, f32), b: (Vec2, f32), tolerance: f32) {
+        assert!(
+            a.0.distance(b.0) <= tolerance && (a.1 - b.1).abs() <= tolerance,
+            "{}: {:?} != {:?}",
+            name,
+            a,
+            b
+        );
+    }
+
+    fn allocators() -> Vec<(&'static str, Box<dyn ThrustAllocator>)> {
+        vec![
+            ("linear program", Box::new(LinearProgramAllocator)),
+            ("pseudo-inverse", Box::new(PseudoInverseAllocator)),
+            ("quadratic", Box::new(QuadraticAllocator)),
+            ("mixed-integer", Box::new(MixedIntegerAllocator::default())),
+        ]
+    }
+
+    #[test]
+    fn allocators_meet_desires_and_saturate_alike() {
+        let engines = ship();
+        for (name, allocator) in allocators() {
+            // Desires the ship can meet exactly. The linear program rounds its activations to
+            // hundredths, so it only agrees with the others that closely.
+            let feasible = [
+                (Vec2::new(0.0, 0.3), 0.0),
+                (Vec2::new(0.05, 0.2), 0.1),
+                (Vec2::new(-0.1, 0.0), -0.2),
+                (Vec2::new(0.0, -0.05), 0.0),
+                (Vec2::new(0.0, 0.0), 0.5),
+            ];
+            for (force, torque) in feasible.iter().copied() {
+                let linear = allocate(&LinearProgramAllocator, &engines, force, torque);
+                let activations = allocate(allocator.as_ref(), &engines, force, torque);
+                assert_close(
+                    name,
+                    achieved(&engines, &activations),
+                    achieved(&engines, &linear),
+                    5e-2,
+                );
+                assert_close(
+                    name,
+                    achieved(&engines, &activations),
+                    desired(&engines, force, torque),
+                    5e-2,
+                );
+            }
+
+            // Turning while pushing this hard needs the right main engine at full thrust, with
+            // the rest of the turn coming from the RCS thrusters.
+            let (force, torque) = (Vec2::new(0.0, 1.9 / 4.5), 0.5);
+            let activations = allocate(allocator.as_ref(), &engines, force, torque);
+            assert!((activations[1] - 1.0).abs() < 1e-2, "{}", name);
+            assert_close(
+                name,
+                achieved(&engines, &activations),
+                desired(&engines, force, torque),
+                5e-2,
+            );
+
+            // More thrust than the ship has saturates the main engines without turning it or
+            // fighting them with the retro engine.
+            let activations = allocate(allocator.as_ref(), &engines, Vec2::new(0.0, 1.0), 0.0);
+            let (force, torque) = achieved(&engines, &activations);
+            assert!(
+                activations[..2].iter().all(|a| (a - 1.0).abs() < 1e-2),
+                "{}",
+                name
+            );
+            assert!(activations[2] < 1e-2, "{}", name);
+            assert!((force.y - 2.0).abs() < 1e-2, "{}", name);
+            assert!(force.x.abs() < 1e-2 && torque.abs() < 1e-2, "{}", name);
+        }
+    }
+
+    #[test]
+    fn fuel_optimal_prefers_the_efficient_engine() {
+        let mut engines = vec![
+            ResolvedEngine::new(Vec2::ZERO, Vec2::new(0.0, 1.0), 1.0, (Entity::new(0), 0)),
+            ResolvedEngine::new(Vec2::ZERO, Vec2::new(0.0, 1.0), 1.0, (Entity::new(0), 1)),
+        ];
+        engines[0].fuel_consumption = 3.0;
+        engines[1].fuel_consumption = 1.0;
+        for (name, allocator) in allocators() {
+            let activations = allocator
+                .allocate(
+                    &engines,
+                    Vec2::ZERO,
+                    Vec2::new(0.0, 0.25),
+                    0.0,
+                    AllocationMode::FuelOptimal,
+                    &ThrusterSolverConfig::default(),
+                )
+                .unwrap();
+            // The linear programs switch the thirsty engine off, the others only lean on the
+            // efficient one in proportion to its efficiency.
+            assert!(activations[0] * 2.0 < activations[1], "{}", name);
+            assert!(
+                (activations[0] + activations[1] - 0.5).abs() < 1e-2,
+                "{}",
+                name
+            );
+
+            let blended = allocate(allocator.as_ref(), &engines, Vec2::new(0.0, 0.25), 0.0);
+            assert!(activations[1] > blended[1] + 0.1, "{}", name);
+        }
+    }
+
+    /// A single engine at the origin pushing along +y, gimbaled half a radian each way and
+    /// resolved into a fan the way `Steering` does it.
+    fn gimbaled_engine() -> Vec<ResolvedEngine> {
+        let gimbal = Gimbal {
+            range: 0.5,
+            slew_rate: 1.0,
+        };
+        gimbal_samples(Some(gimbal))
+            .into_iter()
+            .map(|deflection| ResolvedEngine {
+                thrust_vector: Mat2::from_angle(deflection) * Vec2::new(0.0, 1.0),
+                gimbal: Some(gimbal),
+                deflection,
+                ..ResolvedEngine::new(Vec2::ZERO, Vec2::new(0.0, 1.0), 1.0, (Entity::new(0), 0))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gimbaled_engine_reaches_off_axis_forces() {
+        let direction = Mat2::from_angle(0.3) * Vec2::new(0.0, 1.0);
+        let fixed = [ResolvedEngine::new(
+            Vec2::ZERO,
+            Vec2::new(0.0, 1.0),
+            1.0,
+            (Entity::new(0), 0),
+        )];
+        let activations = allocate(&LinearProgramAllocator, &fixed, direction * 0.5, 0.0);
+        let (force, _) = achieved(&fixed, &activations);
+        assert!(force.x.abs() < 1e-6);
+
+        let engines = gimbaled_engine();
+        assert!(engines.len() > 1);
+        // Only the allocators built on the linear program can keep the fan's combined
+        // activation within one.
+        let allocators: [(&str, &dyn ThrustAllocator); 2] = [
+            ("linear program", &LinearProgramAllocator),
+            ("mixed-integer", &MixedIntegerAllocator::default()),
+        ];
+        for (name, allocator) in allocators {
+            let activations = allocate(allocator, &engines, direction * 0.5, 0.0);
+            assert_close(
+                name,
+                achieved(&engines, &activations),
+                desired(&engines, direction * 0.5, 0.0),
+                2e-2,
+            );
+            let commands = engine_commands(&engines, &activations, ThrottleMode::Proportional);
+            assert_eq!(commands.len(), 1);
+            assert!(
+                (commands[0].deflection.unwrap() - 0.3).abs() < 2e-2,
+                "{}",
+                name
+            );
+            assert!((commands[0].throttle - 0.5).abs() < 2e-2, "{}", name);
+
+            // Asking for more than the engine has saturates the fan as a whole rather than
+            // each of its directions.
+            let activations = allocate(allocator, &engines, direction * 2.0, 0.0);
+            let total: f32 = activations.iter().sum();
+            assert!(total <= 1.0 + 1e-6, "{}: {}", name, total);
+            let (force, _) = achieved(&engines, &activations);
+            assert!(force.length() > 0.9, "{}", name);
+            assert!(force.x * direction.x > 0.0, "{}", name);
+        }
+    }
+}
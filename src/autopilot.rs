@@ -0,0 +1,1553 @@
+use std::f32::consts::{FRAC_PI_4, PI, TAU};
+
+use bevy::{math::Mat2, prelude::*};
+use bevy_rapier2d::{
+    physics::RigidBodyHandleComponent,
+    rapier::dynamics::{RigidBody, RigidBodySet},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::Steering;
+
+/// Turns a ship to a target heading and holds it there by writing `Steering::desired_torque`.
+///
+/// The turn is planned with the ship's actual angular authority, from
+/// `Steering::estimate_acceleration_for`, so it starts braking early enough to stop on target
+/// however weak the ship's engines are.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct HeadingHold {
+    /// The rotation to hold, in radians counterclockwise. At zero the ship's local +y points
+    /// along the world's +y.
+    pub target: f32,
+    /// Turn rate, in radians per second, for each radian of heading error close to the target.
+    pub gain: f32,
+    /// How quickly the ship's turn rate is brought to the planned one, in 1/seconds.
+    pub damping: f32,
+    /// Fraction of the ship's braking authority the turn is planned with, leaving some in
+    /// reserve for errors in the estimate.
+    pub margin: f32,
+}
+
+impl Default for HeadingHold {
+    fn default() -> Self {
+        Self {
+            target: 0.0,
+            gain: 2.0,
+            damping: 8.0,
+            margin: 0.8,
+        }
+    }
+}
+
+impl HeadingHold {
+    pub fn new(target: f32) -> Self {
+        Self {
+            target,
+            ..Default::default()
+        }
+    }
+
+    /// Holds the heading which points the ship's local +y along `direction`.
+    pub fn facing(direction: Vec2) -> Self {
+        Self::new(heading_of(direction))
+    }
+
+    /// The angular acceleration which turns the ship towards `target`, braking in time to
+    /// stop there.
+    pub(crate) fn angular_acceleration(&self, steering: &mut Steering, body: &RigidBody) -> f32 {
+        let error = wrap_angle(self.target - body.position().rotation.angle());
+        // Stopping a turn towards the target takes torque away from it.
+        let braking = angular_authority(steering, body, -error.signum()) * self.margin;
+        let planned_rate = error.signum()
+            * (2.0 * braking * error.abs())
+                .sqrt()
+                .min(self.gain * error.abs());
+        self.damping * (planned_rate - body.angvel())
+    }
+
+    /// Roughly how long these gains take to turn the ship through `angle` from rest.
+    pub(crate) fn turn_time(&self, steering: &mut Steering, body: &RigidBody, angle: f32) -> f32 {
+        let authority = angular_authority(steering, body, angle) * self.margin;
+        if authority > 0.0 {
+            2.0 * (angle.abs() / authority).sqrt() + 1.0 / self.damping
+        } else {
+            f32::INFINITY
+        }
+    }
+}
+
+/// The heading which points a ship's local +y along `direction`.
+pub fn heading_of(direction: Vec2) -> f32 {
+    (-direction.x).atan2(direction.y)
+}
+
+/// The heading which points `body_direction`, in the ship's frame, along `world_direction`.
+pub(crate) fn heading_pointing(body_direction: Vec2, world_direction: Vec2) -> f32 {
+    wrap_angle(
+        world_direction.y.atan2(world_direction.x) - body_direction.y.atan2(body_direction.x),
+    )
+}
+
+/// The angular acceleration the ship can manage turning in the direction of `sign`.
+pub(crate) fn angular_authority(steering: &mut Steering, body: &RigidBody, sign: f32) -> f32 {
+    steering
+        .estimate_acceleration_for(body, Vec2::ZERO, sign.signum())
+        .map_or(0.0, |(_, angular)| (angular * sign.signum()).max(0.0))
+}
+
+/// Wraps an angle into `[-PI, PI)`.
+pub(crate) fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// How much linear acceleration a ship can manage in each direction of its own frame, sampled
+/// from its allocator.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ThrustEnvelope {
+    samples: [f32; ENVELOPE_SAMPLES],
+}
+
+const ENVELOPE_SAMPLES: usize = 16;
+
+impl ThrustEnvelope {
+    /// Asks the allocator for full thrust in each sampled direction. Returns `None` if the
+    /// ship's engine cache hasn't been built yet. The directions are fixed so the firings end
+    /// up in `Steering`'s cache and sampling is cheap after the first time.
+    pub(crate) fn sample(steering: &mut Steering, body: &RigidBody) -> Option<Self> {
+        steering.engines()?;
+        let mut samples = [0.0; ENVELOPE_SAMPLES];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let direction = Self::direction(i);
+            *sample = steering
+                .estimate_acceleration_for(body, direction, 0.0)
+                .map_or(0.0, |(acceleration, _)| {
+                    acceleration.dot(direction).max(0.0)
+                });
+        }
+        Some(Self { samples })
+    }
+
+    fn direction(i: usize) -> Vec2 {
+        Mat2::from_angle(i as f32 * TAU / ENVELOPE_SAMPLES as f32) * Vec2::Y
+    }
+
+    /// The acceleration the ship can manage along `direction`, in its own frame, interpolated
+    /// between the neighbouring samples.
+    pub(crate) fn along(&self, direction: Vec2) -> f32 {
+        let position =
+            (-direction.x).atan2(direction.y).rem_euclid(TAU) * ENVELOPE_SAMPLES as f32 / TAU;
+        let i = position.floor() as usize % ENVELOPE_SAMPLES;
+        let t = position.fract();
+        self.samples[i] * (1.0 - t) + self.samples[(i + 1) % ENVELOPE_SAMPLES] * t
+    }
+
+    /// The direction, in the ship's frame, it can accelerate hardest in and how hard.
+    pub(crate) fn strongest(&self) -> (Vec2, f32) {
+        let (i, acceleration) = self
+            .samples
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+        (Self::direction(i), *acceleration)
+    }
+}
+
+pub(crate) fn heading_hold(
+    bodies: Res<RigidBodySet>,
+    mut query: Query<(&HeadingHold, &mut Steering, &RigidBodyHandleComponent)>,
+) {
+    for (hold, mut steering, body_handle) in query.iter_mut() {
+        if let Some(body) = bodies.get(body_handle.handle()) {
+            let steering = &mut *steering;
+            let angular_acceleration = hold.angular_acceleration(steering, body);
+            steering.request_angular_acceleration(body, angular_acceleration);
+        }
+    }
+}
+
+/// Drives a ship's linear and angular velocity to targets by writing both
+/// `Steering::desired_force` and `Steering::desired_torque`. The default targets are zero, which
+/// brings the ship to a full stop.
+///
+/// Ships which can accelerate much harder in some directions than others, such as ones with
+/// big main engines and weak RCS, turn their strongest engines towards the velocity error
+/// whenever turning and burning would cancel it sooner than thrusting the way they are
+/// pointing.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct VelocityHold {
+    /// The linear velocity to hold, in the world frame.
+    pub linear: Vec2,
+    /// The angular velocity to hold, in radians per second counterclockwise. It is ignored
+    /// while the ship is turning to brake.
+    pub angular: f32,
+    /// How quickly velocity errors are corrected, in 1/seconds.
+    pub gain: f32,
+    /// Linear velocity errors smaller than this, in rapier's units, never make the ship turn.
+    pub tolerance: f32,
+    /// The gains used while turning to brake. Its target is ignored.
+    pub turning: HeadingHold,
+    #[serde(skip)]
+    turning_to: Option<f32>,
+}
+
+impl Default for VelocityHold {
+    fn default() -> Self {
+        Self {
+            linear: Vec2::ZERO,
+            angular: 0.0,
+            gain: 2.0,
+            tolerance: 0.1,
+            turning: HeadingHold::default(),
+            turning_to: None,
+        }
+    }
+}
+
+impl VelocityHold {
+    pub fn new(linear: Vec2, angular: f32) -> Self {
+        Self {
+            linear,
+            angular,
+            ..Default::default()
+        }
+    }
+
+    /// Brings the ship to a stop.
+    pub fn full_stop() -> Self {
+        Self::default()
+    }
+
+    /// True while the ship is turning its strongest engines towards the velocity error.
+    pub fn is_turning(&self) -> bool {
+        self.turning_to.is_some()
+    }
+
+    /// Steers the ship towards the target velocities, given its thrust envelope. If `facing`
+    /// is given the ship keeps its strongest engines pointed that way, in the world frame,
+    /// rather than deciding for itself when to turn.
+    pub(crate) fn steer(
+        &mut self,
+        steering: &mut Steering,
+        body: &RigidBody,
+        envelope: Option<ThrustEnvelope>,
+        facing: Option<Vec2>,
+    ) {
+        let velocity = body.linvel();
+        let error = self.linear - Vec2::new(velocity.x, velocity.y);
+        let rotation = body.position().rotation.angle();
+
+        let heading = envelope.and_then(|envelope| {
+            let (strongest, best) = envelope.strongest();
+            if let Some(facing) = facing {
+                return Some((heading_pointing(strongest, facing), strongest));
+            }
+            if error.length() <= self.tolerance {
+                return None;
+            }
+            let along = envelope.along(Mat2::from_angle(-rotation) * error.normalize());
+            let heading = heading_pointing(strongest, error);
+            let turn = wrap_angle(heading - rotation);
+            let speed = error.length();
+            let direct_time = if along > 0.0 {
+                speed / along
+            } else {
+                f32::INFINITY
+            };
+            let worth_turning =
+                direct_time > speed / best + self.turning.turn_time(steering, body, turn);
+            // Once started, a turn is finished even though thrusting the way the ship points
+            // gets more attractive as it comes round, unless the error swings right round.
+            let continuing = self
+                .turning_to
+                .is_some_and(|target| wrap_angle(heading - target).abs() < FRAC_PI_4);
+            ((continuing || worth_turning) && turn.abs() > ALIGNED).then_some((heading, strongest))
+        });
+        self.turning_to = heading
+            .map(|(heading, _)| heading)
+            .filter(|heading| wrap_angle(heading - rotation).abs() > ALIGNED);
+
+        let acceleration = error * self.gain;
+        let acceleration = match heading {
+            // Only thrust with the strongest engines while turning, and only once they are
+            // nearly pointing the right way, rather than pushing the ship off course.
+            Some((heading, strongest)) if self.is_turning() => {
+                if wrap_angle(heading - rotation).abs() < FRAC_PI_4 {
+                    let strongest = Mat2::from_angle(rotation) * strongest;
+                    strongest * strongest.dot(acceleration).max(0.0)
+                } else {
+                    Vec2::ZERO
+                }
+            }
+            _ => acceleration,
+        };
+        let angular_acceleration = match heading {
+            Some((heading, _)) => HeadingHold {
+                target: heading,
+                ..self.turning
+            }
+            .angular_acceleration(steering, body),
+            None => self.gain * (self.angular - body.angvel()),
+        };
+        steering.request_linear_acceleration(body, acceleration);
+        steering.request_angular_acceleration(body, angular_acceleration);
+    }
+}
+
+/// How close a ship's heading has to get, in radians, before it stops turning to brake.
+const ALIGNED: f32 = 0.05;
+
+pub(crate) fn velocity_hold(
+    bodies: Res<RigidBodySet>,
+    mut query: Query<(&mut VelocityHold, &mut Steering, &RigidBodyHandleComponent)>,
+) {
+    for (mut hold, mut steering, body_handle) in query.iter_mut() {
+        if let Some(body) = bodies.get(body_handle.handle()) {
+            let envelope = ThrustEnvelope::sample(&mut steering, body);
+            hold.steer(&mut steering, body, envelope, None);
+        }
+    }
+}
+
+/// Where a ship flying to a point under `NavigateTo` is in its flight.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum NavigationPhase {
+    Accelerating,
+    /// Flying at `NavigateTo::max_speed`.
+    Coasting,
+    Braking,
+    /// Stopped within `NavigateTo::tolerance` of the target. The ship holds still there until
+    /// it drifts out to twice the tolerance.
+    Arrived,
+}
+
+/// Flies a ship to a point in the world and stops it there, sending
+/// `AutopilotEvent::Arrived` when it gets there.
+///
+/// The flight is planned from the ship's thrust envelope, asked of its allocator: it
+/// accelerates towards the target, coasts if it reaches `max_speed`, and starts braking just
+/// in time to stop on the target. Ships which can brake harder by turning around first, such
+/// as ones with all of their main engines pointing one way, plan for the flip and burn their
+/// main engines to stop.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct NavigateTo {
+    /// The point to stop at, in the world frame and rapier's units.
+    pub target: Vec2,
+    /// How close the ship has to stop to the target to have arrived. It stays arrived out to
+    /// twice this, so that one sitting on the edge doesn't keep arriving.
+    pub tolerance: f32,
+    /// How slow the ship has to be going to have arrived.
+    pub speed_tolerance: f32,
+    /// The fastest the ship will fly.
+    pub max_speed: f32,
+    /// Fraction of the ship's braking authority the flight is planned with, leaving some in
+    /// reserve for errors in the estimate.
+    pub margin: f32,
+    /// The gains used to follow the planned velocity. Its targets are overwritten.
+    pub velocity: VelocityHold,
+    #[serde(skip, default = "accelerating")]
+    phase: NavigationPhase,
+}
+
+fn accelerating() -> NavigationPhase {
+    NavigationPhase::Accelerating
+}
+
+impl Default for NavigateTo {
+    fn default() -> Self {
+        Self {
+            target: Vec2::ZERO,
+            tolerance: 0.5,
+            speed_tolerance: 0.1,
+            max_speed: f32::INFINITY,
+            margin: 0.7,
+            velocity: VelocityHold {
+                gain: 4.0,
+                ..Default::default()
+            },
+            phase: NavigationPhase::Accelerating,
+        }
+    }
+}
+
+impl NavigateTo {
+    pub fn new(target: Vec2) -> Self {
+        Self {
+            target,
+            ..Default::default()
+        }
+    }
+
+    pub fn phase(&self) -> NavigationPhase {
+        self.phase
+    }
+
+    /// The fastest the ship can be closing on a point `distance` away and still be down to
+    /// `exit_speed` there, and whether it should turn around to brake with its strongest
+    /// engines rather than braking with the ones pointing the other way.
+    fn braking_speed(
+        &self,
+        steering: &mut Steering,
+        body: &RigidBody,
+        envelope: &ThrustEnvelope,
+        distance: f32,
+        exit_speed: f32,
+    ) -> (f32, bool) {
+        let (strongest, best) = envelope.strongest();
+        let reverse = envelope.along(-strongest) * self.margin;
+        let direct = (exit_speed * exit_speed + 2.0 * reverse * distance).sqrt();
+        // Until it starts braking, plan for a full half turn from the heading the ship
+        // accelerated on, so that the plan doesn't shift under the ship as it comes round.
+        let turning = &self.velocity.turning;
+        let flip_time = if self.phase == NavigationPhase::Braking {
+            0.0
+        } else {
+            turning
+                .turn_time(steering, body, PI)
+                .max(turning.turn_time(steering, body, -PI))
+        };
+        // Solves `v * flip_time + (v^2 - exit_speed^2) / (2 * best) = distance` for `v`.
+        let best = best * self.margin;
+        let flipped = if best > 0.0 && flip_time.is_finite() {
+            let exit = exit_speed / best;
+            best * (-flip_time
+                + (flip_time * flip_time + 2.0 * distance / best + exit * exit).sqrt())
+        } else {
+            0.0
+        };
+        (direct.max(flipped), flipped > direct)
+    }
+
+    /// Plans and follows the flight, given the ship's thrust envelope, returning true on
+    /// arrival. The target moves with `frame_velocity` and the flight is planned relative to
+    /// it, so the ship arrives moving with it.
+    pub(crate) fn steer(
+        &mut self,
+        steering: &mut Steering,
+        body: &RigidBody,
+        envelope: Option<ThrustEnvelope>,
+        frame_velocity: Vec2,
+    ) -> bool {
+        let position = body.position().translation;
+        let offset = self.target - Vec2::new(position.x, position.y);
+        let velocity = body.linvel();
+        let velocity = Vec2::new(velocity.x, velocity.y) - frame_velocity;
+        let distance = offset.length();
+
+        // Within tolerance the ship just stops, and once arrived it holds still there until
+        // it drifts well out again.
+        let stopping = distance <= self.tolerance
+            || (self.phase == NavigationPhase::Arrived && distance <= 2.0 * self.tolerance);
+        let arrived = stopping
+            && self.phase != NavigationPhase::Arrived
+            && velocity.length() <= self.speed_tolerance;
+        let mut facing = None;
+        let planned = match envelope {
+            Some(envelope) if !stopping => {
+                let direction = offset.normalize_or_zero();
+                let (braking_speed, flips) =
+                    self.braking_speed(steering, body, &envelope, distance, 0.0);
+                let closing = velocity.dot(direction);
+                // Braking carries on until the ship stops closing, so that one which brakes a
+                // little too hard doesn't turn back round to make up the difference.
+                let braking = (self.phase == NavigationPhase::Braking && closing > 0.0)
+                    || (braking_speed < self.max_speed && closing >= braking_speed * 0.95);
+                self.phase = if braking {
+                    NavigationPhase::Braking
+                } else if closing >= self.max_speed * 0.95 {
+                    NavigationPhase::Coasting
+                } else {
+                    NavigationPhase::Accelerating
+                };
+                // Close in on the target proportionally at the end rather than chasing the
+                // infinitely steep braking curve.
+                let mut speed = braking_speed
+                    .min(self.velocity.gain * distance)
+                    .min(self.max_speed);
+                if braking && self.velocity.is_turning() {
+                    // The plan stops allowing for the flip once braking starts, so don't speed
+                    // back up while the ship is still coming round.
+                    speed = speed.min(closing);
+                }
+                if self.phase == NavigationPhase::Braking
+                    && flips
+                    && velocity.length() > self.velocity.tolerance
+                {
+                    facing = Some(-velocity.normalize());
+                }
+                direction * speed
+            }
+            _ => Vec2::ZERO,
+        };
+        if arrived {
+            self.phase = NavigationPhase::Arrived;
+        }
+        self.velocity.linear = planned + frame_velocity;
+        self.velocity.angular = 0.0;
+        self.velocity.steer(steering, body, envelope, facing);
+        arrived
+    }
+}
+
+pub(crate) fn navigate_to(
+    bodies: Res<RigidBodySet>,
+    mut events: EventWriter<AutopilotEvent>,
+    mut query: Query<(
+        Entity,
+        &mut NavigateTo,
+        &mut Steering,
+        &RigidBodyHandleComponent,
+    )>,
+) {
+    for (entity, mut navigation, mut steering, body_handle) in query.iter_mut() {
+        if let Some(body) = bodies.get(body_handle.handle()) {
+            let envelope = ThrustEnvelope::sample(&mut steering, body);
+            if navigation.steer(&mut steering, body, envelope, Vec2::ZERO) {
+                events.send(AutopilotEvent::Arrived(entity));
+            }
+        }
+    }
+}
+
+/// Flies a ship along a path through a list of waypoints, sending
+/// `AutopilotEvent::WaypointReached` as it passes each one and `AutopilotEvent::PathCompleted`
+/// at the end.
+///
+/// The ship steers for a point `lookahead` further along the path than it is, pure pursuit
+/// style, which rounds off the corners. It slows down for each corner to a speed its lateral
+/// authority can hold the curve at, found from the engines in `Steering`'s engine cache, and
+/// stops at the end of the path the way `NavigateTo` does. A looping path is flown
+/// indefinitely, completing once per lap, except that a loop of a single waypoint just stops
+/// there.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct FollowPath {
+    /// The points to fly through, in the world frame and rapier's units.
+    waypoints: Vec<Vec2>,
+    /// Whether the last waypoint leads back to the first.
+    pub looping: bool,
+    /// How far ahead along the path the ship steers for. Longer is smoother but cuts corners
+    /// more. Anything shorter than `MIN_LOOKAHEAD` is flown as that.
+    pub lookahead: f32,
+    /// How close the ship has to come to a waypoint to have reached it. Waypoints the ship
+    /// flies past are reached anyway.
+    pub waypoint_tolerance: f32,
+    /// How the ship flies the path. Its `max_speed`, `margin` and gains are used all along
+    /// the path, and it stops the ship at the end. Its target is overwritten.
+    pub arrival: NavigateTo,
+    #[serde(skip)]
+    next: usize,
+    #[serde(skip)]
+    cornering_speeds: Vec<f32>,
+}
+
+impl Default for FollowPath {
+    fn default() -> Self {
+        Self {
+            waypoints: vec![],
+            looping: false,
+            lookahead: 3.0,
+            waypoint_tolerance: 1.0,
+            arrival: NavigateTo::default(),
+            next: 0,
+            cornering_speeds: vec![],
+        }
+    }
+}
+
+impl FollowPath {
+    pub fn new(waypoints: Vec<Vec2>) -> Self {
+        Self {
+            waypoints,
+            ..Default::default()
+        }
+    }
+
+    /// A path which goes round and round, like a race track.
+    pub fn looping(waypoints: Vec<Vec2>) -> Self {
+        Self {
+            waypoints,
+            looping: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn waypoints(&self) -> &[Vec2] {
+        &self.waypoints
+    }
+
+    /// Replaces the path, starting again from its first waypoint.
+    pub fn set_waypoints(&mut self, waypoints: Vec<Vec2>) {
+        self.waypoints = waypoints;
+        self.next = 0;
+        self.cornering_speeds.clear();
+    }
+
+    /// The index of the waypoint the ship is heading for. Equal to the number of waypoints
+    /// once a path which stops at its end is complete.
+    pub fn next_waypoint(&self) -> usize {
+        self.next
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next >= self.waypoints.len()
+    }
+
+    /// The fastest the ship can take the corner at each waypoint, from its lateral authority
+    /// and position when the path was last flown. Infinite where the path doesn't turn.
+    pub fn cornering_speeds(&self) -> &[f32] {
+        &self.cornering_speeds
+    }
+
+    /// Whether the ship stops at the last waypoint rather than carrying on round.
+    fn stops_at_end(&self) -> bool {
+        !self.looping || self.waypoints.len() == 1
+    }
+
+    fn lookahead(&self) -> f32 {
+        self.lookahead.max(MIN_LOOKAHEAD)
+    }
+
+    fn following(&self, i: usize) -> Option<usize> {
+        if i + 1 < self.waypoints.len() {
+            Some(i + 1)
+        } else if self.looping {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    fn preceding(&self, i: usize) -> Option<usize> {
+        if i > 0 {
+            Some(i - 1)
+        } else if self.looping {
+            self.waypoints.len().checked_sub(1)
+        } else {
+            None
+        }
+    }
+
+    /// The fastest the ship can round a corner at waypoint `i` while steering with this
+    /// lookahead and `lateral` acceleration. Pure pursuit flies a curve of curvature
+    /// `2 sin(a) / lookahead` when the point it is steering for is `a` off its course, which
+    /// is about half the corner's angle. The first waypoint of a path which doesn't loop is
+    /// approached from `start`.
+    fn cornering_speed(&self, i: usize, lateral: f32, start: Vec2) -> f32 {
+        let after = match self.following(i) {
+            Some(after) => after,
+            None => return f32::INFINITY,
+        };
+        let before = self
+            .preceding(i)
+            .map_or(start, |before| self.waypoints[before]);
+        let incoming = (self.waypoints[i] - before).normalize_or_zero();
+        let outgoing = (self.waypoints[after] - self.waypoints[i]).normalize_or_zero();
+        let half_angle = incoming.angle_between(outgoing).abs() / 2.0;
+        let curvature = 2.0 * half_angle.sin() / self.lookahead();
+        if curvature > 1e-6 {
+            (lateral / curvature).sqrt()
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    /// The point `lookahead` further along the path than the closest point to `position` on
+    /// the leg the ship is flying.
+    fn pursuit_point(&self, position: Vec2) -> Vec2 {
+        let to = self.waypoints[self.next];
+        let mut point = match self.preceding(self.next) {
+            Some(before) => {
+                let from = self.waypoints[before];
+                let leg = to - from;
+                let along = (position - from).dot(leg) / leg.length_squared().max(1e-6);
+                from + leg * along.clamp(0.0, 1.0)
+            }
+            None => position,
+        };
+        let mut remaining = self.lookahead();
+        let mut i = self.next;
+        for _ in 0..=self.waypoints.len() {
+            let to = self.waypoints[i];
+            let distance = point.distance(to);
+            if distance >= remaining {
+                return point + (to - point) / distance * remaining;
+            }
+            remaining -= distance;
+            point = to;
+            match self.following(i) {
+                Some(after) => i = after,
+                None => break,
+            }
+        }
+        point
+    }
+
+    /// Whether the ship has reached the waypoint it is heading for, by coming close to it or
+    /// by flying past it.
+    fn reached(&self, position: Vec2) -> bool {
+        let to = self.waypoints[self.next];
+        if position.distance(to) <= self.waypoint_tolerance {
+            return true;
+        }
+        self.preceding(self.next).is_some_and(|before| {
+            let leg = to - self.waypoints[before];
+            (position - to).dot(leg) > 0.0
+        })
+    }
+
+    /// Flies the path, given the ship's thrust envelope, sending events for `entity` as it
+    /// goes.
+    pub(crate) fn steer(
+        &mut self,
+        entity: Entity,
+        steering: &mut Steering,
+        body: &RigidBody,
+        envelope: Option<ThrustEnvelope>,
+        events: &mut EventWriter<AutopilotEvent>,
+    ) {
+        let count = self.waypoints.len();
+        if count == 0 {
+            return;
+        }
+        let position = body.position().translation;
+        let position = Vec2::new(position.x, position.y);
+
+        // The last waypoint of a path which doesn't loop is where the ship stops.
+        if self.stops_at_end() && self.next + 1 >= count {
+            self.arrival.target = self.waypoints[count - 1];
+            let arrived = self.arrival.steer(steering, body, envelope, Vec2::ZERO);
+            if arrived && !self.is_complete() {
+                self.next = count;
+                events.send(AutopilotEvent::WaypointReached(entity, count - 1));
+                events.send(AutopilotEvent::PathCompleted(entity));
+            }
+            return;
+        }
+
+        for _ in 0..count {
+            if !self.reached(position) {
+                break;
+            }
+            events.send(AutopilotEvent::WaypointReached(entity, self.next));
+            match self.following(self.next) {
+                Some(after) => {
+                    if after == 0 {
+                        events.send(AutopilotEvent::PathCompleted(entity));
+                    }
+                    self.next = after;
+                }
+                None => break,
+            }
+            if self.stops_at_end() && self.next + 1 >= count {
+                return self.steer(entity, steering, body, envelope, events);
+            }
+        }
+
+        let envelope = match envelope {
+            Some(envelope) => envelope,
+            None => return,
+        };
+        let (strongest, _) = envelope.strongest();
+        let margin = self.arrival.margin;
+        let lateral = envelope
+            .along(strongest.perp())
+            .min(envelope.along(-strongest.perp()))
+            * margin;
+        self.cornering_speeds = (0..count)
+            .map(|i| self.cornering_speed(i, lateral, position))
+            .collect();
+
+        // Slow down in time for every corner ahead, and for the end of the path, braking the
+        // way the arrival would, turning around first if that is quicker.
+        let mut speed = self.arrival.max_speed;
+        let mut distance = position.distance(self.waypoints[self.next]);
+        let mut i = self.next;
+        for _ in 0..count {
+            let limit = match self.following(i) {
+                Some(_) => self.cornering_speeds[i],
+                None => 0.0,
+            };
+            let (braking_speed, _) = self
+                .arrival
+                .braking_speed(steering, body, &envelope, distance, limit);
+            speed = speed.min(braking_speed);
+            match self.following(i) {
+                Some(after) => {
+                    distance += self.waypoints[i].distance(self.waypoints[after]);
+                    i = after;
+                }
+                None => break,
+            }
+        }
+
+        let direction = (self.pursuit_point(position) - position).normalize_or_zero();
+        let velocity = &mut self.arrival.velocity;
+        velocity.linear = direction * speed;
+        velocity.angular = 0.0;
+        velocity.steer(steering, body, Some(envelope), None);
+    }
+}
+
+/// The shortest lookahead `FollowPath` steers with, in rapier's units. Steering for a point
+/// much closer than this swings the ship from one side of the leg to the other rather than
+/// along it.
+pub const MIN_LOOKAHEAD: f32 = 1.0;
+
+pub(crate) fn follow_path(
+    bodies: Res<RigidBodySet>,
+    mut events: EventWriter<AutopilotEvent>,
+    mut query: Query<(
+        Entity,
+        &mut FollowPath,
+        &mut Steering,
+        &RigidBodyHandleComponent,
+    )>,
+) {
+    for (entity, mut path, mut steering, body_handle) in query.iter_mut() {
+        if let Some(body) = bodies.get(body_handle.handle()) {
+            let envelope = ThrustEnvelope::sample(&mut steering, body);
+            path.steer(entity, &mut steering, body, envelope, &mut events);
+        }
+    }
+}
+
+/// The position and velocity of a body, in the world frame.
+fn motion(body: &RigidBody) -> (Vec2, Vec2) {
+    let position = body.position().translation;
+    let velocity = body.linvel();
+    (
+        Vec2::new(position.x, position.y),
+        Vec2::new(velocity.x, velocity.y),
+    )
+}
+
+/// Where a target at `offset` moving at `relative_velocity` relative to the ship will be
+/// when the ship reaches it, if the ship is closing on it.
+fn lead_point(
+    target: Vec2,
+    offset: Vec2,
+    relative_velocity: Vec2,
+    target_velocity: Vec2,
+) -> Option<Vec2> {
+    let distance = offset.length();
+    let closing = -offset.dot(relative_velocity) / distance.max(1e-6);
+    (closing > 0.0).then(|| target + target_velocity * (distance / closing))
+}
+
+/// Closes on another entity's rigid body as fast as the ship can, using proportional
+/// navigation to stay on a collision course. Good for ramming and short range weapons.
+///
+/// The ship accelerates along the line of sight to the target and against any rotation of
+/// that line, turning its strongest engines that way when it is worth it. It doesn't slow
+/// down, so it flies through the target and comes back round rather than stopping. If the
+/// target has no body the ship just holds its velocity.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Pursue {
+    pub target: Entity,
+    /// How hard the ship turns against the rotation of the line of sight. Three to five is
+    /// usual, higher gets on course sooner.
+    pub navigation_constant: f32,
+    /// The fastest the ship will close on the target.
+    pub max_closing_speed: f32,
+    /// The gains used to follow the guidance. Its targets are overwritten.
+    pub velocity: VelocityHold,
+    #[serde(skip)]
+    lead: Option<Vec2>,
+}
+
+impl Pursue {
+    pub fn new(target: Entity) -> Self {
+        Self {
+            target,
+            navigation_constant: 3.0,
+            max_closing_speed: f32::INFINITY,
+            velocity: VelocityHold::default(),
+            lead: None,
+        }
+    }
+
+    /// Where the ship will meet the target if both keep their current velocities, or `None`
+    /// if it isn't closing on it.
+    pub fn lead_point(&self) -> Option<Vec2> {
+        self.lead
+    }
+
+    /// Steers for a target moving with `target_motion`, given the ship's thrust envelope.
+    pub(crate) fn steer(
+        &mut self,
+        steering: &mut Steering,
+        body: &RigidBody,
+        envelope: Option<ThrustEnvelope>,
+        target_motion: Option<(Vec2, Vec2)>,
+    ) {
+        let (position, velocity) = motion(body);
+        let (target, target_velocity) = match target_motion {
+            Some(target_motion) => target_motion,
+            None => (position, velocity),
+        };
+        let offset = target - position;
+        let relative_velocity = target_velocity - velocity;
+        self.lead = lead_point(target, offset, relative_velocity, target_velocity);
+
+        let distance = offset.length();
+        let acceleration = match envelope {
+            Some(envelope) if target_motion.is_some() && distance > 1e-3 => {
+                let line_of_sight = offset / distance;
+                let closing = -line_of_sight.dot(relative_velocity);
+                let rotation = offset.perp_dot(relative_velocity) / (distance * distance);
+                let (_, best) = envelope.strongest();
+                let along = ((self.max_closing_speed - closing) * self.velocity.gain).min(best);
+                let across = self.navigation_constant * closing.max(0.0) * rotation;
+                line_of_sight * along + line_of_sight.perp() * across
+            }
+            _ => Vec2::ZERO,
+        };
+        // The guidance wants this acceleration for as long as the chase lasts, so the strongest
+        // engines are kept pointed along it rather than weighing up each turn.
+        let facing = (acceleration.length() > 1e-3).then(|| acceleration.normalize());
+        self.velocity.linear = velocity + acceleration / self.velocity.gain;
+        self.velocity.angular = 0.0;
+        self.velocity.steer(steering, body, envelope, facing);
+    }
+}
+
+pub(crate) fn pursue(
+    bodies: Res<RigidBodySet>,
+    handles: Query<&RigidBodyHandleComponent>,
+    mut query: Query<(&mut Pursue, &mut Steering, &RigidBodyHandleComponent)>,
+) {
+    for (mut pursuit, mut steering, body_handle) in query.iter_mut() {
+        if let Some(body) = bodies.get(body_handle.handle()) {
+            let target = handles
+                .get(pursuit.target)
+                .ok()
+                .and_then(|handle| bodies.get(handle.handle()))
+                .map(motion);
+            let envelope = ThrustEnvelope::sample(&mut steering, body);
+            pursuit.steer(&mut steering, body, envelope, target);
+        }
+    }
+}
+
+/// Flies to another entity's rigid body and matches its velocity, for docking, boarding or
+/// flying in formation.
+///
+/// The flight is planned like `NavigateTo`'s but in the target's frame, so the ship leads a
+/// moving target and turns to brake against the relative velocity when that is quicker. It
+/// assumes the target keeps its current velocity and replans every frame as it changes. If
+/// the target has no body the ship just holds its velocity.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Intercept {
+    pub target: Entity,
+    /// How far short of the target the ship stops, on the side it approaches from.
+    pub standoff: f32,
+    /// How the ship flies to the target. Its target is overwritten.
+    pub rendezvous: NavigateTo,
+    #[serde(skip)]
+    lead: Option<Vec2>,
+}
+
+impl Intercept {
+    pub fn new(target: Entity) -> Self {
+        Self {
+            target,
+            standoff: 0.0,
+            rendezvous: NavigateTo::default(),
+            lead: None,
+        }
+    }
+
+    /// Where the ship will meet the target if both keep their current velocities, or `None`
+    /// if it isn't closing on it.
+    pub fn lead_point(&self) -> Option<Vec2> {
+        self.lead
+    }
+
+    /// True once the ship is holding station at the target.
+    pub fn is_matched(&self) -> bool {
+        self.rendezvous.phase() == NavigationPhase::Arrived
+    }
+
+    /// Flies to a target moving with `target_motion`, given the ship's thrust envelope,
+    /// returning true once it has matched the target's velocity.
+    pub(crate) fn steer(
+        &mut self,
+        steering: &mut Steering,
+        body: &RigidBody,
+        envelope: Option<ThrustEnvelope>,
+        target_motion: Option<(Vec2, Vec2)>,
+    ) -> bool {
+        let (position, velocity) = motion(body);
+        let (target, target_velocity) = match target_motion {
+            Some(target_motion) => target_motion,
+            None => {
+                self.lead = None;
+                self.rendezvous.velocity.linear = velocity;
+                self.rendezvous.velocity.angular = 0.0;
+                self.rendezvous
+                    .velocity
+                    .steer(steering, body, envelope, None);
+                return false;
+            }
+        };
+        let offset = target - position;
+        self.lead = lead_point(target, offset, target_velocity - velocity, target_velocity);
+        self.rendezvous.target = target - offset.normalize_or_zero() * self.standoff;
+        self.rendezvous
+            .steer(steering, body, envelope, target_velocity)
+    }
+}
+
+pub(crate) fn intercept(
+    bodies: Res<RigidBodySet>,
+    handles: Query<&RigidBodyHandleComponent>,
+    mut events: EventWriter<AutopilotEvent>,
+    mut query: Query<(
+        Entity,
+        &mut Intercept,
+        &mut Steering,
+        &RigidBodyHandleComponent,
+    )>,
+) {
+    for (entity, mut interception, mut steering, body_handle) in query.iter_mut() {
+        if let Some(body) = bodies.get(body_handle.handle()) {
+            let target = handles
+                .get(interception.target)
+                .ok()
+                .and_then(|handle| bodies.get(handle.handle()))
+                .map(motion);
+            let envelope = ThrustEnvelope::sample(&mut steering, body);
+            if interception.steer(&mut steering, body, envelope, target) {
+                events.send(AutopilotEvent::Intercepted(entity));
+            }
+        }
+    }
+}
+
+/// Sent by the autopilots as ships reach their goals.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AutopilotEvent {
+    /// A ship under `NavigateTo` stopped at its target.
+    Arrived(Entity),
+    /// A ship under `FollowPath` reached the waypoint with the given index.
+    WaypointReached(Entity, usize),
+    /// A ship under `FollowPath` reached the end of its path, or finished a lap of a looping
+    /// one.
+    PathCompleted(Entity),
+    /// A ship under `Intercept` matched its target's velocity.
+    Intercepted(Entity),
+}
+
+impl AutopilotEvent {
+    pub fn entity(&self) -> Entity {
+        match self {
+            AutopilotEvent::Arrived(e)
+            | AutopilotEvent::WaypointReached(e, ..)
+            | AutopilotEvent::PathCompleted(e)
+            | AutopilotEvent::Intercepted(e) => *e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::{Events, ManualEventReader},
+        ecs::schedule::SingleThreadedExecutor,
+    };
+    use bevy_rapier2d::{
+        physics::{RapierConfiguration, RapierPhysicsPlugin},
+        rapier::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder, na::Vector2},
+    };
+
+    use super::*;
+    use crate::{Engine, EngineSet, ThrusterPlugin};
+
+    /// A unit ball with two main engines pushing it along its +y, a weaker retro and RCS
+    /// thrusters to turn and strafe with.
+    fn engines() -> Vec<Engine> {
+        let engine = |offset: (f32, f32), thrust_vector: (f32, f32), max_thrust| Engine {
+            offset: offset.into(),
+            thrust_vector: thrust_vector.into(),
+            max_thrust,
+            ..Default::default()
+        };
+        vec![
+            engine((-1.0, -1.0), (0.0, 1.0), 5.0),
+            engine((1.0, -1.0), (0.0, 1.0), 5.0),
+            engine((0.0, 1.0), (0.0, -1.0), 1.0),
+            engine((0.0, 1.0), (1.0, 0.0), 0.5),
+            engine((0.0, 1.0), (-1.0, 0.0), 0.5),
+            engine((0.0, -1.0), (1.0, 0.0), 0.5),
+            engine((0.0, -1.0), (-1.0, 0.0), 0.5),
+        ]
+    }
+
+    /// A headless world without gravity which steps its physics by a fixed 1/60 s each frame.
+    /// Its systems run one at a time, so the engines fire on the same side of the physics step
+    /// every frame. Which side that is isn't up to us, and varies from run to run.
+    struct Sim {
+        app: App,
+        reader: ManualEventReader<AutopilotEvent>,
+    }
+
+    impl Sim {
+        fn new() -> Self {
+            let mut builder = App::build();
+            builder
+                .add_plugins(MinimalPlugins)
+                .add_plugin(TransformPlugin)
+                .add_plugin(RapierPhysicsPlugin)
+                .add_plugin(ThrusterPlugin)
+                .insert_resource(RapierConfiguration {
+                    gravity: Vector2::zeros(),
+                    ..Default::default()
+                });
+            for stage in [
+                CoreStage::PreUpdate,
+                CoreStage::Update,
+                CoreStage::PostUpdate,
+            ] {
+                builder.stage(stage, |stage: &mut SystemStage| {
+                    stage.set_executor(Box::new(SingleThreadedExecutor::default()));
+                    stage
+                });
+            }
+            Self {
+                app: builder.app,
+                reader: ManualEventReader::default(),
+            }
+        }
+
+        fn ship(&mut self, body: RigidBodyBuilder, autopilot: impl Bundle) -> Entity {
+            self.app
+                .world
+                .spawn()
+                .insert_bundle((
+                    body,
+                    ColliderBuilder::ball(1.0),
+                    Transform::default(),
+                    GlobalTransform::default(),
+                    Steering::default(),
+                    EngineSet(engines()),
+                ))
+                .insert_bundle(autopilot)
+                .id()
+        }
+
+        /// Something to chase which coasts along at a constant velocity. It has no collider,
+        /// so the ship can fly through it.
+        fn target(&mut self, body: RigidBodyBuilder) -> Entity {
+            self.app
+                .world
+                .spawn()
+                .insert_bundle((body, Transform::default(), GlobalTransform::default()))
+                .id()
+        }
+
+        /// Runs a frame and returns the autopilot events sent in it.
+        fn step(&mut self) -> Vec<AutopilotEvent> {
+            self.app.update();
+            let events = self
+                .app
+                .world
+                .get_resource::<Events<AutopilotEvent>>()
+                .unwrap();
+            self.reader.iter(events).copied().collect()
+        }
+
+        fn body(&self, entity: Entity) -> &RigidBody {
+            let handle = self
+                .app
+                .world
+                .get::<RigidBodyHandleComponent>(entity)
+                .unwrap()
+                .handle();
+            let bodies = self.app.world.get_resource::<RigidBodySet>().unwrap();
+            bodies.get(handle).unwrap()
+        }
+
+        fn position(&self, entity: Entity) -> Vec2 {
+            motion(self.body(entity)).0
+        }
+
+        fn velocity(&self, entity: Entity) -> Vec2 {
+            motion(self.body(entity)).1
+        }
+
+        fn rotation(&self, entity: Entity) -> f32 {
+            self.body(entity).position().rotation.angle()
+        }
+    }
+
+    #[test]
+    fn heading_hold_settles_without_overshooting() {
+        let mut sim = Sim::new();
+        let target = 2.0;
+        let ship = sim.ship(RigidBodyBuilder::new_dynamic(), (HeadingHold::new(target),));
+
+        let mut overshoot: f32 = 0.0;
+        for frame in 0..600 {
+            sim.step();
+            let error = wrap_angle(target - sim.rotation(ship));
+            overshoot = overshoot.max(-error);
+            if frame >= 240 {
+                assert!(
+                    error.abs() < 0.01,
+                    "frame {}: heading error {}",
+                    frame,
+                    error
+                );
+                assert!(sim.body(ship).angvel().abs() < 0.01);
+            }
+        }
+        assert!(overshoot < 0.01, "overshot by {}", overshoot);
+    }
+
+    #[test]
+    fn velocity_hold_settles_without_overshooting() {
+        let mut sim = Sim::new();
+        let target = Vec2::new(3.0, 0.0);
+        let hold = VelocityHold::new(target, 0.0);
+        // Pushing sideways is weak, so the ship turns its main engines towards the target.
+        let ship = sim.ship(RigidBodyBuilder::new_dynamic(), (hold,));
+
+        for frame in 0..600 {
+            sim.step();
+            let velocity = sim.velocity(ship);
+            assert!(
+                velocity.x < target.x + hold.tolerance,
+                "frame {}: {}",
+                frame,
+                velocity
+            );
+            if frame >= 240 {
+                let error = velocity.distance(target);
+                assert!(
+                    error < hold.tolerance,
+                    "frame {}: velocity error {}",
+                    frame,
+                    error
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn full_stop_stops_the_ship() {
+        let mut sim = Sim::new();
+        let ship = sim.ship(
+            RigidBodyBuilder::new_dynamic()
+                .linvel(4.0, -2.0)
+                .angvel(1.0),
+            (VelocityHold::full_stop(),),
+        );
+
+        for frame in 0..600 {
+            sim.step();
+            if frame >= 300 {
+                let speed = sim.velocity(ship).length();
+                assert!(speed < 0.02, "frame {}: still going at {}", frame, speed);
+                assert!(sim.body(ship).angvel().abs() < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn navigate_to_stops_at_the_target_and_arrives_once() {
+        let mut sim = Sim::new();
+        let target = Vec2::new(30.0, 20.0);
+        let navigation = NavigateTo::new(target);
+        let ship = sim.ship(RigidBodyBuilder::new_dynamic(), (navigation,));
+
+        let arrival = (0..900).find(|_| {
+            let events = sim.step();
+            assert!(events
+                .iter()
+                .all(|event| *event == AutopilotEvent::Arrived(ship)));
+            !events.is_empty()
+        });
+        assert!(arrival.is_some(), "never arrived");
+        assert!(sim.position(ship).distance(target) <= navigation.tolerance);
+
+        // Sitting at the target doesn't arrive again, even if it creeps back and forth over
+        // the edge of the tolerance, by thousandths a second too little for its engines to
+        // correct.
+        for _ in 0..300 {
+            assert!(sim.step().is_empty());
+            assert!(sim.position(ship).distance(target) <= 2.0 * navigation.tolerance);
+            assert!(sim.velocity(ship).length() <= navigation.speed_tolerance);
+        }
+        let navigation = sim.app.world.get::<NavigateTo>(ship).unwrap();
+        assert_eq!(navigation.phase(), NavigationPhase::Arrived);
+    }
+
+    #[test]
+    fn follow_path_reaches_each_waypoint_and_completes_once() {
+        let mut sim = Sim::new();
+        let waypoints = vec![
+            Vec2::new(15.0, 0.0),
+            Vec2::new(15.0, 15.0),
+            Vec2::new(0.0, 15.0),
+        ];
+        let path = FollowPath::new(waypoints.clone());
+        let ship = sim.ship(RigidBodyBuilder::new_dynamic(), (path.clone(),));
+
+        let mut events = vec![];
+        let mut stopped = None;
+        for _ in 0..1800 {
+            let new_events = sim.step();
+            if new_events.contains(&AutopilotEvent::PathCompleted(ship)) {
+                stopped = Some((sim.position(ship), sim.velocity(ship)));
+            }
+            events.extend(new_events);
+        }
+        assert_eq!(
+            events,
+            vec![
+                AutopilotEvent::WaypointReached(ship, 0),
+                AutopilotEvent::WaypointReached(ship, 1),
+                AutopilotEvent::WaypointReached(ship, 2),
+                AutopilotEvent::PathCompleted(ship),
+            ]
+        );
+        assert!(sim.app.world.get::<FollowPath>(ship).unwrap().is_complete());
+        // It completes stopped at the end, and holds roughly there afterwards.
+        let (position, velocity) = stopped.unwrap();
+        assert!(position.distance(waypoints[2]) <= path.arrival.tolerance);
+        assert!(velocity.length() <= path.arrival.speed_tolerance);
+        assert!(sim.position(ship).distance(waypoints[2]) <= path.waypoint_tolerance);
+    }
+
+    #[test]
+    fn looping_path_completes_each_lap_and_slows_for_corners() {
+        let mut sim = Sim::new();
+        let corners = vec![
+            Vec2::new(20.0, 0.0),
+            Vec2::new(20.0, 20.0),
+            Vec2::new(0.0, 20.0),
+            Vec2::new(0.0, 0.0),
+        ];
+        let ship = sim.ship(
+            RigidBodyBuilder::new_dynamic(),
+            (FollowPath::looping(corners.clone()),),
+        );
+
+        let mut events = vec![];
+        let mut top_speed: f32 = 0.0;
+        let mut corner_speeds = vec![f32::INFINITY; corners.len()];
+        let mut laps = 0;
+        for _ in 0..6000 {
+            if laps == 2 {
+                break;
+            }
+            let new_events = sim.step();
+            laps += new_events
+                .iter()
+                .filter(|event| **event == AutopilotEvent::PathCompleted(ship))
+                .count();
+            events.extend(new_events);
+            let (position, speed) = (sim.position(ship), sim.velocity(ship).length());
+            top_speed = top_speed.max(speed);
+            for (corner, corner_speed) in corners.iter().zip(&mut corner_speeds) {
+                if position.distance(*corner) < 3.0 {
+                    *corner_speed = corner_speed.min(speed);
+                }
+            }
+        }
+
+        // The waypoints come round in order, with a completion after the last one each lap.
+        let mut expected = vec![];
+        for i in (0..corners.len()).cycle() {
+            if expected.len() >= events.len() {
+                break;
+            }
+            expected.push(AutopilotEvent::WaypointReached(ship, i));
+            if i == corners.len() - 1 {
+                expected.push(AutopilotEvent::PathCompleted(ship));
+            }
+        }
+        assert_eq!(events, expected);
+        assert_eq!(laps, 2);
+
+        let path = sim.app.world.get::<FollowPath>(ship).unwrap();
+        assert!(path
+            .cornering_speeds()
+            .iter()
+            .all(|speed| speed.is_finite()));
+        for (i, corner_speed) in corner_speeds.iter().enumerate() {
+            assert!(
+                *corner_speed < top_speed / 2.0,
+                "took corner {} at {} with a top speed of {}",
+                i,
+                corner_speed,
+                top_speed
+            );
+        }
+    }
+
+    #[test]
+    fn single_waypoint_loop_completes_once() {
+        let mut sim = Sim::new();
+        let ship = sim.ship(
+            RigidBodyBuilder::new_dynamic(),
+            (FollowPath::looping(vec![Vec2::new(5.0, 5.0)]),),
+        );
+
+        let mut events = vec![];
+        for _ in 0..900 {
+            events.extend(sim.step());
+        }
+        assert_eq!(
+            events,
+            vec![
+                AutopilotEvent::WaypointReached(ship, 0),
+                AutopilotEvent::PathCompleted(ship),
+            ]
+        );
+    }
+
+    #[test]
+    fn replacing_a_path_restarts_it_and_zero_lookahead_is_flown() {
+        let mut sim = Sim::new();
+        let path = FollowPath {
+            lookahead: 0.0,
+            ..FollowPath::new(vec![
+                Vec2::new(5.0, 0.0),
+                Vec2::new(10.0, 0.0),
+                Vec2::new(10.0, 5.0),
+                Vec2::new(15.0, 5.0),
+            ])
+        };
+        let ship = sim.ship(RigidBodyBuilder::new_dynamic(), (path,));
+        let next = |sim: &Sim| {
+            sim.app
+                .world
+                .get::<FollowPath>(ship)
+                .unwrap()
+                .next_waypoint()
+        };
+        let assert_finite_desires = |sim: &Sim| {
+            let steering = sim.app.world.get::<Steering>(ship).unwrap();
+            assert!(steering.desired_force.is_finite() && steering.desired_torque.is_finite());
+        };
+
+        let reached = (0..1200).find(|_| {
+            sim.step();
+            assert_finite_desires(&sim);
+            next(&sim) == 3
+        });
+        assert!(reached.is_some(), "never got to the last leg");
+
+        let target = Vec2::new(10.0, 10.0);
+        sim.app
+            .world
+            .get_mut::<FollowPath>(ship)
+            .unwrap()
+            .set_waypoints(vec![target]);
+        assert_eq!(next(&sim), 0);
+        let mut events = vec![];
+        for _ in 0..900 {
+            events.extend(sim.step());
+            assert_finite_desires(&sim);
+        }
+        assert_eq!(
+            events,
+            vec![
+                AutopilotEvent::WaypointReached(ship, 0),
+                AutopilotEvent::PathCompleted(ship),
+            ]
+        );
+    }
+
+    #[test]
+    fn pursue_turns_back_for_a_target_it_is_flying_away_from_and_closes_on_it() {
+        let mut sim = Sim::new();
+        let target = sim.target(
+            RigidBodyBuilder::new_dynamic()
+                .translation(20.0, 10.0)
+                .linvel(1.0, 0.0),
+        );
+        let ship = sim.ship(
+            RigidBodyBuilder::new_dynamic().linvel(-3.0, 0.0),
+            (Pursue::new(target),),
+        );
+        let lead_point = |sim: &Sim| sim.app.world.get::<Pursue>(ship).unwrap().lead_point();
+
+        sim.step();
+        assert_eq!(lead_point(&sim), None);
+        let mut led = false;
+        let mut closest = f32::INFINITY;
+        for _ in 0..1200 {
+            sim.step();
+            led |= lead_point(&sim).is_some();
+            closest = closest.min(sim.position(ship).distance(sim.position(target)));
+        }
+        assert!(led);
+        assert!(closest < 1.0, "only got within {}", closest);
+    }
+
+    #[test]
+    fn intercept_matches_a_moving_target_and_reports_it_once() {
+        let mut sim = Sim::new();
+        let target_velocity = Vec2::new(1.5, 0.5);
+        let target = sim.target(
+            RigidBodyBuilder::new_dynamic()
+                .translation(15.0, 10.0)
+                .linvel(target_velocity.x, target_velocity.y),
+        );
+        let interception = Intercept {
+            standoff: 2.0,
+            ..Intercept::new(target)
+        };
+        let ship = sim.ship(RigidBodyBuilder::new_dynamic(), (interception,));
+        let intercept = |sim: &Sim| *sim.app.world.get::<Intercept>(ship).unwrap();
+
+        let mut led = false;
+        let intercepted = (0..1800).find(|_| {
+            let events = sim.step();
+            led |= intercept(&sim).lead_point().is_some();
+            !events.is_empty()
+        });
+        assert!(intercepted.is_some(), "never intercepted");
+        assert!(led);
+        assert!(intercept(&sim).is_matched());
+        let distance = sim.position(ship).distance(sim.position(target));
+        assert!((distance - interception.standoff).abs() <= interception.rendezvous.tolerance);
+
+        // It then flies in formation with the target without intercepting it again.
+        for _ in 0..300 {
+            assert!(sim.step().is_empty());
+            assert!(intercept(&sim).is_matched());
+            let relative_velocity = sim.velocity(ship) - target_velocity;
+            assert!(relative_velocity.length() <= interception.rendezvous.speed_tolerance);
+        }
+    }
+
+    #[test]
+    fn pursue_and_intercept_hold_velocity_without_a_target_body() {
+        let mut sim = Sim::new();
+        let nothing = sim.app.world.spawn().id();
+        let velocity = Vec2::new(2.0, 1.0);
+        let body = || {
+            RigidBodyBuilder::new_dynamic()
+                .rotation(-velocity.x.atan2(velocity.y))
+                .linvel(velocity.x, velocity.y)
+        };
+        let pursuer = sim.ship(body(), (Pursue::new(nothing),));
+        let interceptor = sim.ship(body().translation(0.0, 10.0), (Intercept::new(nothing),));
+
+        for _ in 0..300 {
+            assert!(sim.step().is_empty());
+            for ship in [pursuer, interceptor] {
+                assert!((sim.velocity(ship) - velocity).length() < 0.1);
+            }
+            let world = &sim.app.world;
+            assert_eq!(world.get::<Pursue>(pursuer).unwrap().lead_point(), None);
+            assert_eq!(
+                world.get::<Intercept>(interceptor).unwrap().lead_point(),
+                None
+            );
+        }
+    }
+}
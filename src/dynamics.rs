@@ -0,0 +1,218 @@
+use bevy::math::Mat2;
+
+use crate::{allocator::EngineCommand, Boost, Heat};
+
+/// Once an engine's throttle is this close to its command it snaps the rest of the way, so
+/// spooling engines actually reach zero and stop.
+const SPOOL_EPSILON: f32 = 1e-3;
+
+/// What an engine is physically doing, which lags behind what the allocator commands when
+/// the engine has spool times or a gimbal slew rate.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct EngineState {
+    /// The fraction of max thrust the engine is actually producing.
+    pub throttle: f32,
+    /// The throttle the engine was last commanded to, which `throttle` spools toward.
+    pub commanded_throttle: f32,
+    /// The current gimbal deflection in radians, relative to the engine's thrust vector.
+    pub gimbal_angle: f32,
+    /// Heat built up by the engine. Always zero for engines without a `Heat` model.
+    pub heat: f32,
+    /// The engine got hotter than `Heat::max_heat` and hasn't cooled off yet.
+    pub overheated: bool,
+    /// Seconds of full boost the engine has used out of `Boost::duration`.
+    pub boost_used: f32,
+    /// The engine is running above its unboosted rating.
+    pub boosting: bool,
+}
+
+impl EngineState {
+    /// Advances the state `dt` seconds toward `command` and returns the command as the engine
+    /// actually carries it out.
+    pub(crate) fn step(&mut self, command: &EngineCommand, dt: f32) -> EngineCommand {
+        let mut actual = *command;
+        self.commanded_throttle = command.throttle;
+        if let Some(gimbal) = command.gimbal {
+            // A commanded thrust vector already includes its deflection. One without a
+            // deflection is the neutral direction and the engine holds its current angle.
+            let (deflection, neutral) = match command.deflection {
+                Some(deflection) => (
+                    deflection,
+                    Mat2::from_angle(-deflection) * command.thrust_vector,
+                ),
+                None => (self.gimbal_angle, command.thrust_vector),
+            };
+            let max_slew = gimbal.slew_rate * dt;
+            self.gimbal_angle += (deflection - self.gimbal_angle).clamp(-max_slew, max_slew);
+            actual.thrust_vector = Mat2::from_angle(self.gimbal_angle) * neutral;
+            actual.deflection = Some(self.gimbal_angle);
+        }
+
+        let time_constant = if command.throttle > self.throttle {
+            command.spool_up_time
+        } else {
+            command.spool_down_time
+        };
+        self.throttle = if time_constant > 0.0 {
+            let throttle = self.throttle
+                + (command.throttle - self.throttle) * (1.0 - (-dt / time_constant).exp());
+            if (command.throttle - throttle).abs() < SPOOL_EPSILON {
+                command.throttle
+            } else {
+                throttle
+            }
+        } else {
+            command.throttle
+        };
+        actual.throttle = self.throttle;
+        actual
+    }
+
+    /// Heats the engine for `dt` seconds at `load`, its thrust as a fraction of its unboosted
+    /// rating, and cools it. Returns true if the engine overheated or recovered.
+    pub(crate) fn update_heat(&mut self, heat: &Heat, load: f32, dt: f32) -> bool {
+        self.heat = (self.heat + (heat.heating_rate * load - heat.cooling_rate) * dt).max(0.0);
+        let overheated = if self.overheated {
+            self.heat > heat.max_heat * 0.5
+        } else {
+            self.heat > heat.max_heat
+        };
+        let changed = overheated != self.overheated;
+        self.overheated = overheated;
+        changed
+    }
+
+    /// Runs the engine for `dt` seconds at `usage` of its boost, from
+    /// `EngineCommand::boost_usage`. Returns true if the budget is used up.
+    pub(crate) fn update_boost(&mut self, boost: &Boost, usage: f32, dt: f32) -> bool {
+        self.boost_used += usage * dt;
+        self.boosting = usage > 0.0;
+        let exhausted = self.boost_used >= boost.duration;
+        if exhausted {
+            self.boosting = false;
+        }
+        exhausted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::*;
+    use crate::{allocator, Engine, Gimbal, ResolvedEngine, Steering, ThrottleMode};
+
+    /// The state of the single engine in the heat and boost tests.
+    fn state(steering: &mut Steering) -> &mut EngineState {
+        steering
+            .engine_states
+            .entry((Entity::new(0), 0))
+            .or_default()
+    }
+
+    #[test]
+    fn engine_spooling_down_keeps_its_deflection() {
+        let mut engine =
+            ResolvedEngine::new(Vec2::ZERO, Vec2::new(0.0, 1.0), 1.0, (Entity::new(0), 0));
+        engine.gimbal = Some(Gimbal {
+            range: 0.5,
+            slew_rate: 1.0,
+        });
+        engine.spool_down_time = 1.0;
+        let mut state = EngineState {
+            throttle: 1.0,
+            gimbal_angle: 0.3,
+            ..Default::default()
+        };
+
+        let off = allocator::engine_commands(&[engine], &[0.0], ThrottleMode::Proportional);
+        let actual = state.step(&off[0], 0.1);
+        assert!(actual.throttle > 0.0);
+        assert!((state.gimbal_angle - 0.3).abs() < 1e-6);
+        let expected = Mat2::from_angle(0.3) * Vec2::new(0.0, 1.0);
+        assert!(actual.thrust_vector.distance(expected) < 1e-6);
+    }
+
+    #[test]
+    fn overheated_engine_shuts_off_until_below_half_its_max_heat() {
+        let heat = Heat {
+            heating_rate: 2.0,
+            cooling_rate: 1.0,
+            max_heat: 10.0,
+            overheated_thrust: 0.0,
+        };
+        let engine = Engine {
+            heat: Some(heat),
+            ..Default::default()
+        };
+        let id = (Entity::new(0), 0);
+        let mut steering = Steering::default();
+        fn state(steering: &mut Steering) -> &mut EngineState {
+            steering
+                .engine_states
+                .entry((Entity::new(0), 0))
+                .or_default()
+        }
+
+        // At full load the engine nets one unit of heat a second.
+        let mut steps = 0;
+        while !state(&mut steering).update_heat(&heat, 1.0, 0.1) {
+            assert_eq!(steering.available_thrust(&engine, id), 1.0);
+            steps += 1;
+        }
+        assert!((99..=101).contains(&steps));
+        assert!(state(&mut steering).overheated);
+        assert_eq!(steering.available_thrust(&engine, id), 0.0);
+
+        // Cooling back below max heat isn't enough, it has to get to half of it.
+        let mut steps = 0;
+        while !state(&mut steering).update_heat(&heat, 0.0, 0.1) {
+            assert!(state(&mut steering).overheated);
+            steps += 1;
+        }
+        assert!((49..=51).contains(&steps));
+        assert!(state(&mut steering).heat <= 5.0);
+        assert_eq!(steering.available_thrust(&engine, id), 1.0);
+    }
+
+    #[test]
+    fn boost_multiplies_thrust_until_its_budget_is_used() {
+        let boost = Boost {
+            thrust_multiplier: 2.0,
+            duration: 1.0,
+        };
+        let engine = Engine {
+            boost: Some(boost),
+            ..Default::default()
+        };
+        let id = (Entity::new(0), 0);
+        let mut steering = Steering::default();
+        assert_eq!(steering.available_thrust(&engine, id), 1.0);
+        steering.allow_boost = true;
+        assert_eq!(steering.available_thrust(&engine, id), 2.0);
+
+        // Three quarters of the boosted rating is half way into the boost.
+        let mut resolved = ResolvedEngine::new(Vec2::ZERO, Vec2::new(0.0, 1.0), 2.0, id);
+        resolved.boost = Some(boost);
+        let command =
+            allocator::engine_commands(&[resolved], &[0.75], ThrottleMode::Proportional)[0];
+        assert!((command.load() - 1.5).abs() < 1e-6);
+        let usage = command.boost_usage();
+        assert!((usage - 0.5).abs() < 1e-6);
+
+        // Half boost makes the one second budget last two.
+        let mut steps = 0;
+        while !state(&mut steering).update_boost(&boost, usage, 0.1) {
+            assert!(state(&mut steering).boosting);
+            steps += 1;
+        }
+        assert!((19..=20).contains(&steps));
+        assert!(!state(&mut steering).boosting);
+        assert_eq!(steering.available_thrust(&engine, id), 1.0);
+
+        // Nothing comes back over time, only when the budget is refilled.
+        assert!(state(&mut steering).update_boost(&boost, 0.0, 100.0));
+        steering.refill_boost(id);
+        assert_eq!(steering.available_thrust(&engine, id), 2.0);
+    }
+}
@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier2d::{
+    physics::{RapierConfiguration, RigidBodyHandleComponent},
+    rapier::{dynamics::MassProperties, dynamics::RigidBodySet, math::Point},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Steering, ThrusterError};
+
+/// Propellant for a ship's engines. Without a `Plumbing` component a ship's engines draw from
+/// the tanks on the ship entity and on its children, emptying them in that order.
+///
+/// `amount` is a mass, in the same units as the ship's rigid body, and is added to the body
+/// at the tank's position.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct FuelTank {
+    pub capacity: f32,
+    pub amount: f32,
+    /// The kind of propellant in the tank, matched against `PropellantRatio::propellant`. A tank
+    /// without one only feeds engines which don't list their propellants.
+    #[serde(default)]
+    pub propellant: Option<Propellant>,
+}
+
+impl FuelTank {
+    /// A tank filled to `capacity`.
+    pub fn full(capacity: f32) -> Self {
+        Self {
+            capacity,
+            amount: capacity,
+            propellant: None,
+        }
+    }
+
+    pub fn with_propellant(mut self, propellant: Propellant) -> Self {
+        self.propellant = Some(propellant);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.amount <= 0.0
+    }
+}
+
+/// A kind of propellant. The ids mean whatever the game wants them to, tanks and engines just
+/// have to agree on them.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub struct Propellant(pub u32);
+
+/// One of the propellants an engine burns and its share of the engine's consumption. Shares
+/// are relative, so an oxidizer at `2.5` and a fuel at `1.0` burn in a 2.5:1 mixture.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct PropellantRatio {
+    pub propellant: Propellant,
+    pub ratio: f32,
+}
+
+/// The most propellants a single engine can burn.
+pub const MAX_PROPELLANTS: usize = 3;
+
+/// The propellants an engine burns and their mixture. An empty mixture burns whatever is in the
+/// engine's tanks.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Mixture([Option<PropellantRatio>; MAX_PROPELLANTS]);
+
+impl Mixture {
+    /// A mixture of `ratios`. Panics if there are more than `MAX_PROPELLANTS` of them.
+    pub fn new(ratios: &[PropellantRatio]) -> Self {
+        assert!(
+            ratios.len() <= MAX_PROPELLANTS,
+            "an engine burns at most {} propellants",
+            MAX_PROPELLANTS
+        );
+        let mut mixture = Self::default();
+        for (slot, ratio) in mixture.0.iter_mut().zip(ratios) {
+            *slot = Some(*ratio);
+        }
+        mixture
+    }
+
+    /// An engine burning nothing but `propellant`.
+    pub fn single(propellant: Propellant) -> Self {
+        Self::new(&[PropellantRatio {
+            propellant,
+            ratio: 1.0,
+        }])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PropellantRatio> {
+        self.0.iter().flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+/// Which tanks feed which engines. Put this next to a ship's `Steering` to replace the default
+/// of every engine drawing from every tank on the ship.
+///
+/// Tanks have to be on the ship entity or one of its children, since their propellant is part
+/// of the ship's mass. Any others are ignored and reported as `ThrusterError::ForeignTank`.
+#[derive(Clone, Debug, Default)]
+pub struct Plumbing {
+    /// The tanks feeding each engine directly, keyed by the entity carrying the engine's
+    /// `EngineSet` and the engine's index in it. Engines without an entry have no fuel.
+    pub feeds: HashMap<(Entity, usize), Vec<Entity>>,
+    pub crossfeeds: Vec<Crossfeed>,
+}
+
+/// A line which lets engines fed by `to` also draw from `from`, once their own tanks of that
+/// propellant are dry. Crossfeeds only go one way and aren't chained.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Crossfeed {
+    pub from: Entity,
+    pub to: Entity,
+}
+
+impl Plumbing {
+    /// Every tank the plumbing refers to, in no particular order and possibly repeated.
+    fn tanks(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.feeds
+            .values()
+            .flatten()
+            .copied()
+            .chain(self.crossfeeds.iter().flat_map(|c| [c.from, c.to]))
+    }
+}
+
+/// The ship entity followed by its children, which is where its tanks can be.
+fn ship_entities(
+    ship: Entity,
+    maybe_children: Option<&Children>,
+) -> impl Iterator<Item = Entity> + '_ {
+    std::iter::once(ship).chain(maybe_children.into_iter().flat_map(|c| c.iter().copied()))
+}
+
+/// The tanks an engine can draw from, in the order it draws from them.
+pub(crate) fn engine_tanks(
+    ship: Entity,
+    maybe_children: Option<&Children>,
+    maybe_plumbing: Option<&Plumbing>,
+    engine: (Entity, usize),
+) -> Vec<Entity> {
+    match maybe_plumbing {
+        None => ship_entities(ship, maybe_children).collect(),
+        Some(plumbing) => {
+            let direct = plumbing
+                .feeds
+                .get(&engine)
+                .map_or(&[][..], |t| t.as_slice());
+            let mut tanks = direct.to_vec();
+            for crossfeed in &plumbing.crossfeeds {
+                if direct.contains(&crossfeed.to) && !tanks.contains(&crossfeed.from) {
+                    tanks.push(crossfeed.from);
+                }
+            }
+            tanks.retain(|tank| ship_entities(ship, maybe_children).any(|e| e == *tank));
+            tanks
+        }
+    }
+}
+
+/// Splits an engine's mixture into each propellant's share of its consumption. An engine
+/// which doesn't list any propellants burns whatever is in its tanks.
+fn shares(mixture: &Mixture) -> Vec<(Option<Propellant>, f32)> {
+    if mixture.is_empty() {
+        return vec![(None, 1.0)];
+    }
+    let total: f32 = mixture.iter().map(|p| p.ratio).sum();
+    mixture
+        .iter()
+        .map(|p| (Some(p.propellant), p.ratio / total))
+        .collect()
+}
+
+fn burns(tank: &FuelTank, propellant: Option<Propellant>) -> bool {
+    !tank.is_empty() && propellant.map_or(true, |p| tank.propellant == Some(p))
+}
+
+/// The fraction of `amount` of an engine's mixture which `tanks` can supply. For an `amount`
+/// of zero this is 1 if the engine has any of everything it burns and 0 otherwise.
+fn supply(tanks: &[FuelTank], mixture: &Mixture, amount: f32) -> f32 {
+    let mut fraction: f32 = 1.0;
+    for (propellant, share) in shares(mixture) {
+        let available: f32 = tanks
+            .iter()
+            .filter(|tank| burns(tank, propellant))
+            .map(|tank| tank.amount)
+            .sum();
+        let needed = amount * share;
+        fraction = if available <= 0.0 {
+            0.0
+        } else if needed > 0.0 {
+            fraction.min(available / needed)
+        } else {
+            fraction
+        };
+    }
+    fraction
+}
+
+/// Draws `amount` of an engine's mixture from `tanks`, in order, and returns the fraction of it
+/// that was available. Propellants are always drawn in the right mixture, so if one runs
+/// short the others are only drawn to match.
+fn draw(tanks: &mut [FuelTank], mixture: &Mixture, amount: f32) -> f32 {
+    let fraction = supply(tanks, mixture, amount);
+    for (propellant, share) in shares(mixture) {
+        let mut remaining = amount * share * fraction;
+        for tank in tanks.iter_mut() {
+            if remaining <= 0.0 {
+                break;
+            }
+            if burns(tank, propellant) {
+                let drawn = tank.amount.min(remaining);
+                tank.amount -= drawn;
+                remaining -= drawn;
+            }
+        }
+    }
+    fraction
+}
+
+/// The tanks among `entities`, with the entities that have one.
+fn tanks_of(
+    tank_query: &Query<&mut FuelTank>,
+    entities: &[Entity],
+) -> (Vec<Entity>, Vec<FuelTank>) {
+    entities
+        .iter()
+        .filter_map(|entity| {
+            let tank = tank_query.get_component::<FuelTank>(*entity).ok()?;
+            Some((*entity, *tank))
+        })
+        .unzip()
+}
+
+/// `supply` for the tanks on `entities`.
+pub(crate) fn available_fuel(
+    tank_query: &Query<&mut FuelTank>,
+    entities: &[Entity],
+    mixture: &Mixture,
+    amount: f32,
+) -> f32 {
+    supply(&tanks_of(tank_query, entities).1, mixture, amount)
+}
+
+/// `draw` from the tanks on `entities`. Tanks which weren't drawn from aren't marked changed.
+pub(crate) fn draw_fuel(
+    tank_query: &mut Query<&mut FuelTank>,
+    entities: &[Entity],
+    mixture: &Mixture,
+    amount: f32,
+) -> f32 {
+    let (entities, mut tanks) = tanks_of(tank_query, entities);
+    let fraction = draw(&mut tanks, mixture, amount);
+    for (entity, tank) in entities.into_iter().zip(tanks) {
+        if let Ok(mut stored) = tank_query.get_mut(entity) {
+            if *stored != tank {
+                *stored = tank;
+            }
+        }
+    }
+    fraction
+}
+
+/// Keeps the mass of each ship's remaining propellant on its rigid body. Tanks on the ship
+/// entity sit at its origin and tanks on children sit at the child's `Transform`.
+pub(crate) fn update_propellant_mass(
+    rapier_config: Res<RapierConfiguration>,
+    mut body_set: ResMut<RigidBodySet>,
+    mut ship_query: Query<(
+        Entity,
+        &mut Steering,
+        &RigidBodyHandleComponent,
+        Option<&Children>,
+    )>,
+    tank_query: Query<(&FuelTank, Option<&Transform>)>,
+) {
+    for (ship, mut steering, body_handle, maybe_children) in ship_query.iter_mut() {
+        let mut propellant = MassProperties::new(Point::origin(), 0.0, 0.0);
+        for entity in ship_entities(ship, maybe_children) {
+            if let Ok((tank, maybe_transform)) = tank_query.get(entity) {
+                if tank.is_empty() {
+                    continue;
+                }
+                let offset = match maybe_transform {
+                    Some(transform) if entity != ship => {
+                        transform.translation.truncate() / rapier_config.scale
+                    }
+                    _ => Vec2::ZERO,
+                };
+                propellant += MassProperties::new(Point::new(offset.x, offset.y), tank.amount, 0.0);
+            }
+        }
+
+        if propellant == steering.propellant_mass {
+            continue;
+        }
+        if let Some(body) = body_set.get_mut(body_handle.handle()) {
+            let dry = *body.mass_properties() - steering.propellant_mass;
+            body.set_mass_properties(dry + propellant, true);
+            steering.propellant_mass = propellant;
+        }
+    }
+}
+
+/// Reports tanks in a ship's `Plumbing` which aren't on the ship, and so are never drawn from.
+pub(crate) fn validate_plumbing(
+    mut error_events: EventWriter<ThrusterError>,
+    ship_query: Query<(Entity, &Plumbing, Option<&Children>), Changed<Plumbing>>,
+) {
+    for (ship, plumbing, maybe_children) in ship_query.iter() {
+        let mut foreign: Vec<Entity> = plumbing
+            .tanks()
+            .filter(|tank| !ship_entities(ship, maybe_children).any(|e| e == *tank))
+            .collect();
+        foreign.sort();
+        foreign.dedup();
+        for tank in foreign {
+            error_events.send(ThrusterError::ForeignTank(ship, tank));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::{Events, ManualEventReader};
+    use bevy_rapier2d::{
+        physics::RapierPhysicsPlugin,
+        rapier::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder},
+    };
+
+    use super::*;
+    use crate::{Engine, EngineEvent, EngineSet, ThrusterPlugin};
+
+    const OXIDIZER: Propellant = Propellant(0);
+    const FUEL: Propellant = Propellant(1);
+
+    fn bipropellant() -> Mixture {
+        Mixture::new(&[
+            PropellantRatio {
+                propellant: OXIDIZER,
+                ratio: 2.5,
+            },
+            PropellantRatio {
+                propellant: FUEL,
+                ratio: 1.0,
+            },
+        ])
+    }
+
+    fn tank(propellant: Propellant, amount: f32) -> FuelTank {
+        FuelTank {
+            amount,
+            ..FuelTank::full(10.0).with_propellant(propellant)
+        }
+    }
+
+    /// The body's mass and its moment of inertia about its center of mass.
+    fn body_mass(app: &App, ship: Entity) -> (f32, f32) {
+        let handle = app
+            .world
+            .get::<RigidBodyHandleComponent>(ship)
+            .unwrap()
+            .handle();
+        let body_set = app.world.get_resource::<RigidBodySet>().unwrap();
+        let mass_properties = body_set.get(handle).unwrap().mass_properties();
+        (
+            1.0 / mass_properties.inv_mass,
+            1.0 / mass_properties.inv_principal_inertia_sqrt.powi(2),
+        )
+    }
+
+    #[test]
+    fn burning_propellant_lightens_the_body() {
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(RapierPhysicsPlugin)
+            .add_plugin(ThrusterPlugin);
+        let mut tank = None;
+        let ship = builder
+            .world_mut()
+            .spawn()
+            .insert_bundle((
+                RigidBodyBuilder::new_dynamic(),
+                ColliderBuilder::ball(1.0),
+                Steering::default(),
+            ))
+            .with_children(|ship| {
+                tank = Some(
+                    ship.spawn_bundle((FuelTank::full(2.0), Transform::from_xyz(3.0, 0.0, 0.0)))
+                        .id(),
+                );
+            })
+            .id();
+        let tank = tank.unwrap();
+        let mut app = builder.app;
+
+        // A unit ball, with the whole tank three units off center.
+        let dry = (std::f32::consts::PI, std::f32::consts::FRAC_PI_2);
+        let set_amount = |app: &mut App, amount| {
+            app.world.get_mut::<FuelTank>(tank).unwrap().amount = amount;
+            app.update();
+            body_mass(app, ship)
+        };
+        let full = set_amount(&mut app, 2.0);
+        let half = set_amount(&mut app, 1.0);
+        let empty = set_amount(&mut app, 0.0);
+
+        assert!((full.0 - dry.0 - 2.0).abs() < 1e-4);
+        assert!((half.0 - dry.0 - 1.0).abs() < 1e-4);
+        assert!(full.1 > half.1 && half.1 > empty.1 + 1.0);
+        assert!((empty.0 - dry.0).abs() < 1e-4);
+        assert!((empty.1 - dry.1).abs() < 1e-4);
+    }
+
+    #[test]
+    fn plumbing_routes_feeds_and_crossfeeds_on_the_ship() {
+        let [ship, main, drop, rcs, foreign] = [0, 1, 2, 3, 4].map(Entity::new);
+        let children = Children::with(&[main, drop, rcs]);
+        let plumbing = Plumbing {
+            feeds: vec![((ship, 0), vec![main, foreign]), ((ship, 1), vec![rcs])]
+                .into_iter()
+                .collect(),
+            crossfeeds: vec![
+                Crossfeed {
+                    from: drop,
+                    to: main,
+                },
+                Crossfeed {
+                    from: main,
+                    to: rcs,
+                },
+            ],
+        };
+        let tanks = |plumbing, engine| engine_tanks(ship, Some(&children), plumbing, engine);
+
+        // Own tanks first, then crossfed ones, without following crossfeeds any further and
+        // without the tank that isn't on the ship.
+        assert_eq!(tanks(Some(&plumbing), (ship, 0)), vec![main, drop]);
+        assert_eq!(tanks(Some(&plumbing), (ship, 1)), vec![rcs, main]);
+        assert!(tanks(Some(&plumbing), (ship, 2)).is_empty());
+        assert_eq!(tanks(None, (ship, 2)), vec![ship, main, drop, rcs]);
+    }
+
+    #[test]
+    fn plumbing_reports_tanks_off_the_ship() {
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(RapierPhysicsPlugin)
+            .add_plugin(ThrusterPlugin);
+        let world = builder.world_mut();
+        let foreign = world.spawn().insert(FuelTank::full(1.0)).id();
+        let mut own = None;
+        let ship = world
+            .spawn()
+            .insert(Steering::default())
+            .with_children(|ship| own = Some(ship.spawn_bundle((FuelTank::full(1.0),)).id()))
+            .id();
+        let mut feeds = HashMap::new();
+        feeds.insert((ship, 0), vec![own.unwrap(), foreign]);
+        world.entity_mut(ship).insert(Plumbing {
+            feeds,
+            crossfeeds: vec![Crossfeed {
+                from: foreign,
+                to: own.unwrap(),
+            }],
+        });
+        let mut app = builder.app;
+        let mut reader = ManualEventReader::<ThrusterError>::default();
+        let mut errors = |app: &mut App| {
+            app.update();
+            let events = app.world.get_resource::<Events<ThrusterError>>().unwrap();
+            reader
+                .iter(events)
+                .map(|error| match error {
+                    ThrusterError::ForeignTank(s, t) => (*s, *t),
+                    other => panic!("unexpected error {:?}", other),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(errors(&mut app), vec![(ship, foreign)]);
+        assert!(errors(&mut app).is_empty());
+    }
+
+    #[test]
+    fn short_propellant_limits_the_whole_mixture() {
+        let mixture = bipropellant();
+        let mut tanks = [tank(FUEL, 0.5), tank(OXIDIZER, 5.0), tank(FUEL, 0.5)];
+
+        // 7 of a 2.5:1 mixture is 5 oxidizer and 2 fuel, and there is only 1 fuel.
+        assert!((supply(&tanks, &mixture, 7.0) - 0.5).abs() < 1e-6);
+        assert_eq!(supply(&tanks, &mixture, 0.0), 1.0);
+        assert!((draw(&mut tanks, &mixture, 7.0) - 0.5).abs() < 1e-6);
+        assert_eq!(tanks[0].amount, 0.0);
+        assert!((tanks[1].amount - 2.5).abs() < 1e-6);
+        assert!(tanks[2].amount.abs() < 1e-6);
+
+        // With no fuel left nothing at all is drawn, even though there's oxidizer.
+        assert_eq!(supply(&tanks, &mixture, 0.0), 0.0);
+        assert_eq!(draw(&mut tanks, &mixture, 1.0), 0.0);
+        assert!((tanks[1].amount - 2.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tanks_are_drawn_in_order_and_only_for_their_propellant() {
+        let mut tanks = [tank(FUEL, 1.0), tank(OXIDIZER, 1.0), tank(FUEL, 1.0)];
+        assert_eq!(draw(&mut tanks, &Mixture::single(FUEL), 1.5), 1.0);
+        assert_eq!(
+            tanks.map(|tank| tank.amount),
+            [0.0, 1.0, 0.5],
+            "the first fuel tank is emptied before the second is touched"
+        );
+
+        // An engine without a mixture burns anything, but an untyped tank only feeds those.
+        let mut untyped = [FuelTank::full(1.0)];
+        assert_eq!(supply(&untyped, &Mixture::single(FUEL), 0.0), 0.0);
+        assert_eq!(draw(&mut untyped, &Mixture::default(), 0.25), 1.0);
+        assert_eq!(draw(&mut tanks, &Mixture::default(), 1.0), 1.0);
+        assert_eq!(tanks.map(|tank| tank.amount), [0.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn empty_tank_stops_its_engines_until_refilled() {
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(RapierPhysicsPlugin)
+            .add_plugin(ThrusterPlugin);
+        let ship = builder
+            .world_mut()
+            .spawn()
+            .insert_bundle((
+                RigidBodyBuilder::new_dynamic(),
+                ColliderBuilder::ball(1.0),
+                Transform::default(),
+                GlobalTransform::default(),
+                Steering {
+                    desired_force: Vec2::new(0.0, 0.5),
+                    ..Default::default()
+                },
+                EngineSet(vec![Engine {
+                    fuel_consumption: 1.0,
+                    ..Default::default()
+                }]),
+                FuelTank {
+                    amount: 0.0,
+                    ..FuelTank::full(1.0)
+                },
+            ))
+            .id();
+        let mut app = builder.app;
+        let mut reader = ManualEventReader::<EngineEvent>::default();
+        let mut events = |app: &mut App| {
+            app.update();
+            let events = app.world.get_resource::<Events<EngineEvent>>().unwrap();
+            reader
+                .iter(events)
+                .map(|event| match event {
+                    EngineEvent::OutOfFuel(e, 0) if *e == ship => "out of fuel",
+                    EngineEvent::StartedFiring(e, 0, _) if *e == ship => "started",
+                    _ => "other",
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(events(&mut app), vec!["out of fuel"]);
+        assert!(events(&mut app).is_empty());
+
+        app.world.get_mut::<FuelTank>(ship).unwrap().amount = 1.0;
+        assert_eq!(events(&mut app), vec!["started"]);
+    }
+}
@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+use bevy_rapier2d::{physics::ColliderHandleComponent, rapier::geometry::NarrowPhase};
+use serde::{Deserialize, Serialize};
+
+use crate::{EngineDamage, EngineSet};
+
+/// Makes the engines on an entity take damage when their collider is hit hard enough. The
+/// entity needs an `EngineSet` and a rapier collider, usually attached to the ship's rigid
+/// body as a child.
+///
+/// In bevy_rapier2d 0.9 a child's collider is placed on the body by
+/// `ColliderBuilder::translation`, not by the child's `Transform`, so give the builder the
+/// same offset as the engines.
+///
+/// After each frame with a physics step, the impulses of all of the collider's contacts in
+/// that step are added up. Any impulse over `threshold` damages every engine on the entity by
+/// `damage_per_impulse` times the excess, through `EngineDamage`. A ship resting against
+/// something takes no further damage unless it pushes harder than `threshold` in a single
+/// step. When rapier runs several steps in one frame only the last is seen.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ImpactDamage {
+    pub threshold: f32,
+    pub damage_per_impulse: f32,
+}
+
+pub(crate) fn impact_damage(
+    narrow_phase: Res<NarrowPhase>,
+    mut damage_events: EventWriter<EngineDamage>,
+    engine_query: Query<(Entity, &EngineSet, &ColliderHandleComponent, &ImpactDamage)>,
+) {
+    // Rapier only touches the narrow phase when it steps, so without a change the impulses
+    // are the ones which have already been counted.
+    if !narrow_phase.is_changed() {
+        return;
+    }
+    for (entity, engine_set, collider, impact_damage) in engine_query.iter() {
+        let contacts = match narrow_phase.contacts_with(collider.handle()) {
+            Some(contacts) => contacts,
+            None => continue,
+        };
+        let impulse: f32 = contacts
+            .filter(|(_, _, pair)| pair.has_any_active_contact)
+            .flat_map(|(_, _, pair)| pair.manifolds.iter())
+            .flat_map(|manifold| manifold.points.iter())
+            .map(|point| point.data.impulse.abs())
+            .sum();
+        if impulse > impact_damage.threshold {
+            let damage = (impulse - impact_damage.threshold) * impact_damage.damage_per_impulse;
+            for i in 0..engine_set.0.len() {
+                damage_events.send(EngineDamage::Damage(entity, i, damage));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::{Events, ManualEventReader};
+    use bevy_rapier2d::{
+        physics::{RapierConfiguration, RapierPhysicsPlugin},
+        rapier::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder, na::Vector2},
+    };
+
+    use super::*;
+    use crate::{Engine, EngineEvent, ThrusterPlugin};
+
+    fn damaged(app: &mut App, reader: &mut ManualEventReader<EngineEvent>) -> usize {
+        app.update();
+        let events = app.world.get_resource::<Events<EngineEvent>>().unwrap();
+        reader
+            .iter(events)
+            .filter(|event| matches!(event, EngineEvent::Damaged(..)))
+            .count()
+    }
+
+    /// An app with a wall three units to the right of the origin.
+    fn app_with_wall() -> AppBuilder {
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(RapierPhysicsPlugin)
+            .add_plugin(ThrusterPlugin)
+            .insert_resource(RapierConfiguration {
+                gravity: Vector2::zeros(),
+                ..Default::default()
+            });
+        builder.world_mut().spawn().insert_bundle((
+            RigidBodyBuilder::new_static().translation(3.0, 0.0),
+            ColliderBuilder::cuboid(0.5, 10.0),
+        ));
+        builder
+    }
+
+    #[test]
+    fn hard_impact_damages_engines_once() {
+        let mut builder = app_with_wall();
+        builder.world_mut().spawn().insert_bundle((
+            RigidBodyBuilder::new_dynamic().linvel(20.0, 0.0),
+            ColliderBuilder::ball(1.0),
+            EngineSet(vec![Engine::default()]),
+            ImpactDamage {
+                threshold: 1.0,
+                damage_per_impulse: 0.001,
+            },
+        ));
+        let mut app = builder.app;
+        let mut reader = ManualEventReader::default();
+
+        let hit = (0..30).position(|_| damaged(&mut app, &mut reader) > 0);
+        assert!(hit.is_some());
+
+        // Without physics steps the impulses from the hit must not be counted again.
+        let set_active = |app: &mut App, active| {
+            app.world
+                .get_resource_mut::<RapierConfiguration>()
+                .unwrap()
+                .physics_pipeline_active = active;
+        };
+        set_active(&mut app, false);
+        for _ in 0..5 {
+            assert_eq!(damaged(&mut app, &mut reader), 0);
+        }
+        set_active(&mut app, true);
+        for _ in 0..30 {
+            assert_eq!(damaged(&mut app, &mut reader), 0);
+        }
+    }
+
+    #[test]
+    fn hard_impact_damages_engines_on_a_child_collider() {
+        let mut builder = app_with_wall();
+        let mut pod = None;
+        builder
+            .world_mut()
+            .spawn()
+            .insert(RigidBodyBuilder::new_dynamic().linvel(20.0, 0.0))
+            .with_children(|ship| {
+                pod = Some(
+                    ship.spawn_bundle((
+                        ColliderBuilder::ball(0.5).translation(1.0, 0.0),
+                        EngineSet(vec![Engine::default()]),
+                        ImpactDamage {
+                            threshold: 1.0,
+                            damage_per_impulse: 0.001,
+                        },
+                    ))
+                    .id(),
+                );
+            });
+        let pod = pod.unwrap();
+        let mut app = builder.app;
+        let mut reader = ManualEventReader::default();
+
+        // The damage goes to the child's engines, since the body itself has no collider.
+        let hit = (0..30).position(|_| damaged(&mut app, &mut reader) > 0);
+        assert!(hit.is_some());
+        let health = app.world.get::<EngineSet>(pod).unwrap().0[0].health;
+        assert!(health < 1.0);
+    }
+}
@@ -1,24 +1,58 @@
+mod allocator;
+mod autopilot;
+mod dynamics;
+mod fuel;
+mod impact;
+mod mixed_integer;
 mod optimizer;
+mod pseudo_inverse;
+mod quadratic;
+
+pub use allocator::{AllocationMode, LinearProgramAllocator, ResolvedEngine, ThrustAllocator};
+pub use autopilot::{
+    heading_of, AutopilotEvent, FollowPath, HeadingHold, Intercept, NavigateTo, NavigationPhase,
+    Pursue, VelocityHold, MIN_LOOKAHEAD,
+};
+pub use dynamics::EngineState;
+pub use fuel::{
+    Crossfeed, FuelTank, Mixture, Plumbing, Propellant, PropellantRatio, MAX_PROPELLANTS,
+};
+pub use impact::ImpactDamage;
+pub use mixed_integer::MixedIntegerAllocator;
+pub use pseudo_inverse::PseudoInverseAllocator;
+pub use quadratic::QuadraticAllocator;
 
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
 
 use bevy::app::Events;
+use bevy::math::Mat2;
 use bevy::prelude::*;
 use bevy_rapier2d::{
     physics::{RapierConfiguration, RigidBodyHandleComponent},
     rapier::{
-        dynamics::{RigidBody, RigidBodySet},
+        dynamics::{MassProperties, RigidBody, RigidBodySet},
         math::{Point, Vector},
     },
 };
 
 const CACHE_COARSENESS: f32 = std::f32::consts::PI / 1000.0;
+/// How many firings each ship keeps. Autopilots and the world-frame command modes ask for a
+/// slightly different desire nearly every frame, so the least recently used firings are
+/// dropped rather than letting the cache grow for the life of the ship.
+const FIRINGS_CACHE_CAPACITY: usize = 64;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
 pub enum SystemLabels {
     InvalidateCaches,
     FireEngines,
+    Autopilot,
+    DamageEngines,
+    ImpactDamage,
+    UpdatePropellantMass,
+    ApplyShipSettings,
 }
 
 #[derive(Default)]
@@ -29,21 +63,97 @@ impl Plugin for ThrusterPlugin {
         if !app.world().contains_resource::<ThrustScale>() {
             app.world_mut().insert_resource(ThrustScale::default());
         }
+        if !app.world().contains_resource::<ThrusterSolverConfig>() {
+            app.world_mut()
+                .insert_resource(ThrusterSolverConfig::default());
+        }
         let cache_system = invalidate_caches
             .system()
             .label(SystemLabels::InvalidateCaches);
         app.register_type::<EngineSet>()
             .add_event::<EngineEvent>()
+            .add_event::<ThrusterError>()
+            .add_event::<EngineDamage>()
+            .add_event::<AutopilotEvent>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                impact::impact_damage
+                    .system()
+                    .label(SystemLabels::ImpactDamage)
+                    .before(SystemLabels::DamageEngines),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                damage_engines
+                    .system()
+                    .label(SystemLabels::DamageEngines)
+                    .before(SystemLabels::InvalidateCaches),
+            )
             .add_system_to_stage(CoreStage::PostUpdate, cache_system)
+            .add_system_to_stage(CoreStage::PostUpdate, validate_engines.system())
+            .add_system_to_stage(CoreStage::PostUpdate, fuel::validate_plumbing.system())
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                apply_ship_settings
+                    .system()
+                    .label(SystemLabels::ApplyShipSettings),
+            )
             .add_system(
                 fire_engines
                     .system()
                     .label(SystemLabels::FireEngines)
                     .after(SystemLabels::InvalidateCaches),
+            )
+            .add_system(
+                autopilot::heading_hold
+                    .system()
+                    .label(SystemLabels::Autopilot)
+                    .before(SystemLabels::FireEngines),
+            )
+            .add_system(
+                autopilot::velocity_hold
+                    .system()
+                    .label(SystemLabels::Autopilot)
+                    .before(SystemLabels::FireEngines),
+            )
+            .add_system(
+                autopilot::navigate_to
+                    .system()
+                    .label(SystemLabels::Autopilot)
+                    .before(SystemLabels::FireEngines),
+            )
+            .add_system(
+                autopilot::follow_path
+                    .system()
+                    .label(SystemLabels::Autopilot)
+                    .before(SystemLabels::FireEngines),
+            )
+            .add_system(
+                autopilot::pursue
+                    .system()
+                    .label(SystemLabels::Autopilot)
+                    .before(SystemLabels::FireEngines),
+            )
+            .add_system(
+                autopilot::intercept
+                    .system()
+                    .label(SystemLabels::Autopilot)
+                    .before(SystemLabels::FireEngines),
+            )
+            .add_system(
+                fuel::update_propellant_mass
+                    .system()
+                    .label(SystemLabels::UpdatePropellantMass)
+                    .after(SystemLabels::FireEngines),
             );
     }
 }
 
+/// Multiplies the thrust of every engine, for all ships as a resource or for one ship as a
+/// component next to its `Steering`.
+///
+/// A ship's component replaces the resource's scale, it isn't multiplied by it.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ThrustScale(pub f32);
 impl Default for ThrustScale {
     fn default() -> Self {
@@ -51,11 +161,105 @@ impl Default for ThrustScale {
     }
 }
 
+/// Weights used by the optimizer when trading off force accuracy, torque accuracy and fuel use.
+///
+/// Inserted as a resource this applies to every ship. Inserted as a component next to a
+/// `Steering` it overrides the resource for that ship only.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ThrusterSolverConfig {
+    /// Weight of torque error, as a multiple of the ship's total thrust.
+    pub torque_weight: f32,
+    /// Weight of force error.
+    pub force_weight: f32,
+    /// Cost of each unit of engine activation.
+    pub fuel_consumption_weight: f32,
+}
+impl Default for ThrusterSolverConfig {
+    fn default() -> Self {
+        Self {
+            torque_weight: 10.0,
+            force_weight: 1.0,
+            fuel_consumption_weight: 0.0001,
+        }
+    }
+}
+
+/// Lets an engine swing its thrust vector to either side of `Engine::thrust_vector`.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Gimbal {
+    /// Maximum deflection to either side of the engine's thrust vector, in radians.
+    pub range: f32,
+    /// Maximum rate the deflection can change, in radians per second.
+    pub slew_rate: f32,
+}
+
+/// Lets an engine run hotter than it can sustain for a while. Heat builds up in proportion to
+/// throttle and bleeds away at a constant rate, so the engine can run indefinitely at a
+/// throttle of `cooling_rate / heating_rate`. Above `max_heat` the engine overheats and is
+/// derated to `overheated_thrust` until it has cooled to half of `max_heat`.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Heat {
+    /// Heat gained per second at full throttle.
+    pub heating_rate: f32,
+    /// Heat lost per second.
+    pub cooling_rate: f32,
+    pub max_heat: f32,
+    /// Fraction of its thrust an overheated engine can still produce. Zero shuts it down.
+    pub overheated_thrust: f32,
+}
+
+/// Lets an engine run above its `max_thrust`, like an afterburner, for a limited time. The
+/// boost is only used by ships with `Steering::allow_boost` set.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Boost {
+    /// The engine's boosted thrust as a multiple of `max_thrust`.
+    pub thrust_multiplier: f32,
+    /// Seconds the engine can run at full boost. Partial boost uses the budget more slowly.
+    pub duration: f32,
+}
+
 #[derive(Copy, Clone, Serialize, Deserialize, Debug)]
 pub struct Engine {
     pub offset: Vec2,
     pub thrust_vector: Vec2,
     pub max_thrust: f32,
+    #[serde(default)]
+    pub gimbal: Option<Gimbal>,
+    /// The lowest fraction of `max_thrust` the engine can run at without shutting off.
+    #[serde(default)]
+    pub min_throttle: f32,
+    /// The engine is either off or at full thrust, like an RCS puffer or a solid booster.
+    #[serde(default)]
+    pub discrete: bool,
+    /// Time constant, in seconds, of the engine's thrust rising toward a higher command.
+    /// Zero means the engine reaches its commanded thrust immediately.
+    #[serde(default)]
+    pub spool_up_time: f32,
+    /// Time constant, in seconds, of the engine's thrust falling toward a lower command.
+    #[serde(default)]
+    pub spool_down_time: f32,
+    /// Fuel drawn from the ship's `FuelTank`s per second at full thrust. Zero means the
+    /// engine doesn't need fuel.
+    #[serde(default)]
+    pub fuel_consumption: f32,
+    /// The propellants the engine burns and their mixture. Empty means the engine burns
+    /// whatever is in its tanks.
+    #[serde(default)]
+    pub propellants: Mixture,
+    /// Fraction of `max_thrust` the engine can still produce after being damaged.
+    #[serde(default = "full_health")]
+    pub health: f32,
+    /// The engine has been destroyed and produces no thrust at all.
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub heat: Option<Heat>,
+    #[serde(default)]
+    pub boost: Option<Boost>,
+}
+
+fn full_health() -> f32 {
+    1.0
 }
 impl Default for Engine {
     fn default() -> Self {
@@ -63,29 +267,338 @@ impl Default for Engine {
             offset: Vec2::splat(0.0),
             thrust_vector: Vec2::new(0.0, 1.0),
             max_thrust: 1.0,
+            gimbal: None,
+            min_throttle: 0.0,
+            discrete: false,
+            spool_up_time: 0.0,
+            spool_down_time: 0.0,
+            fuel_consumption: 0.0,
+            propellants: Mixture::default(),
+            health: 1.0,
+            disabled: false,
+            heat: None,
+            boost: None,
         }
     }
 }
 bevy::reflect::impl_reflect_value!(Engine);
+impl Engine {
+    /// The engine's efficiency, as thrust per unit of propellant burned per second. This is
+    /// specific impulse expressed as an effective exhaust velocity rather than in seconds.
+    /// Infinite for engines which don't need fuel.
+    pub fn specific_impulse(&self) -> f32 {
+        self.max_thrust / self.fuel_consumption
+    }
+
+    /// Sets `fuel_consumption` from a specific impulse, as defined by `specific_impulse`.
+    pub fn with_specific_impulse(mut self, specific_impulse: f32) -> Self {
+        self.fuel_consumption = self.max_thrust / specific_impulse;
+        self
+    }
+
+    /// The thrust the engine can actually produce, after damage.
+    pub fn effective_max_thrust(&self) -> f32 {
+        if self.disabled {
+            0.0
+        } else {
+            self.max_thrust * self.health
+        }
+    }
+
+    /// Checks that the engine is something the optimizer can work with.
+    pub fn validate(&self) -> Result<(), EngineError> {
+        if !self.offset.is_finite() {
+            return Err(EngineError::NonFiniteOffset);
+        }
+        if !self.thrust_vector.is_finite() || self.thrust_vector.length_squared() == 0.0 {
+            return Err(EngineError::InvalidThrustVector);
+        }
+        if !self.max_thrust.is_finite() || self.max_thrust < 0.0 {
+            return Err(EngineError::InvalidMaxThrust);
+        }
+        if !(0.0..=1.0).contains(&self.health) {
+            return Err(EngineError::InvalidHealth);
+        }
+        if !(0.0..=1.0).contains(&self.min_throttle) {
+            return Err(EngineError::InvalidMinThrottle);
+        }
+        if !(self.spool_up_time.is_finite()
+            && self.spool_up_time >= 0.0
+            && self.spool_down_time.is_finite()
+            && self.spool_down_time >= 0.0)
+        {
+            return Err(EngineError::InvalidSpoolTime);
+        }
+        if !self.fuel_consumption.is_finite() || self.fuel_consumption < 0.0 {
+            return Err(EngineError::InvalidFuelConsumption);
+        }
+        if self
+            .propellants
+            .iter()
+            .any(|p| !p.ratio.is_finite() || p.ratio <= 0.0)
+        {
+            return Err(EngineError::InvalidMixture);
+        }
+        if let Some(heat) = self.heat {
+            let rate_valid = |rate: f32| rate.is_finite() && rate >= 0.0;
+            let valid = rate_valid(heat.heating_rate)
+                && rate_valid(heat.cooling_rate)
+                && heat.max_heat.is_finite()
+                && heat.max_heat > 0.0
+                && (0.0..=1.0).contains(&heat.overheated_thrust);
+            if !valid {
+                return Err(EngineError::InvalidHeat);
+            }
+        }
+        if let Some(boost) = self.boost {
+            if !(boost.thrust_multiplier.is_finite()
+                && boost.thrust_multiplier >= 1.0
+                && boost.duration.is_finite()
+                && boost.duration >= 0.0)
+            {
+                return Err(EngineError::InvalidBoost);
+            }
+        }
+        if let Some(gimbal) = self.gimbal {
+            if !(0.0..=std::f32::consts::PI).contains(&gimbal.range)
+                || gimbal.slew_rate.is_nan()
+                || gimbal.slew_rate <= 0.0
+            {
+                return Err(EngineError::InvalidGimbal);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the activations produced by the optimizer are turned into actual thrust.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub enum ThrottleMode {
+    /// Each engine fires at exactly the fraction of its max thrust the optimizer asked for.
+    #[default]
+    Proportional,
+    /// Any engine the optimizer asked for at all fires at full thrust.
+    BangBang,
+}
+impl ThrottleMode {
+    /// Maps an optimizer activation in `[0, 1]` to the throttle actually applied to the engine.
+    pub fn throttle(&self, firing: f32) -> f32 {
+        match self {
+            ThrottleMode::Proportional => firing.clamp(0.0, 1.0),
+            ThrottleMode::BangBang => {
+                if firing > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// How `Steering::desired_force` and `Steering::desired_torque` are interpreted.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+pub enum CommandMode {
+    /// In the ship's frame, normalized so that a force of length one or a torque of one asks
+    /// for everything the engines can give.
+    #[default]
+    BodyNormalized,
+    /// Like `BodyNormalized` but the force is in the world frame.
+    WorldNormalized,
+    /// A force in the world frame and a torque, in rapier's units.
+    Force,
+    /// A linear acceleration in the world frame and an angular acceleration, in rapier's
+    /// units. The ship's mass and moment of inertia are taken into account.
+    Acceleration,
+}
+
+fn body_center_of_mass(body: &RigidBody) -> Vec2 {
+    let center_of_mass = body.mass_properties().local_com;
+    Vec2::new(center_of_mass.x, center_of_mass.y)
+}
+
+/// `value / limit`, or zero if either is zero so that asking for nothing, or for something the
+/// ship can't do at all, asks for nothing.
+fn normalize(value: f32, limit: f32) -> f32 {
+    if value == 0.0 || limit == 0.0 {
+        0.0
+    } else {
+        value / limit
+    }
+}
+
+/// The activation of each of a ship's engines, or the reason the allocator couldn't find one.
+type Firing = Result<Vec<f32>, SolveError>;
+
 #[derive(Reflect, Default, Debug)]
 pub struct EngineSet(pub Vec<Engine>);
 
-#[derive(Default)]
 pub struct Steering {
     pub desired_force: Vec2,
     pub desired_torque: f32,
+    pub throttle_mode: ThrottleMode,
+    pub allocation_mode: AllocationMode,
+    pub command_mode: CommandMode,
+    /// Lets the allocator use the boosted rating of engines with a `Boost` until their
+    /// budgets run out. Desires are normalized against the boosted thrust while it lasts.
+    pub allow_boost: bool,
     last_seen_center_of_mass: Vec2,
-    firings_cache: HashMap<(i32, i32, i32), Vec<f32>>,
-    engines: Option<Vec<(Vec2, Vec2, f32, (Entity, usize))>>,
+    last_seen_allow_boost: bool,
+    last_seen_allocation_mode: AllocationMode,
+    solver_config: ThrusterSolverConfig,
+    thrust_scale: f32,
+    allocator: Arc<dyn ThrustAllocator>,
+    /// Firings keyed on the quantized desire, with when each was last used.
+    firings_cache: HashMap<(i32, i32, i32), (Firing, u64)>,
+    firings_cache_clock: u64,
+    engines: Option<Vec<ResolvedEngine>>,
     currently_firing: HashSet<(Entity, usize)>,
+    engine_states: HashMap<(Entity, usize), EngineState>,
+    dry_engines: HashSet<(Entity, usize)>,
+    propellant_mass: MassProperties,
+}
+
+impl Default for Steering {
+    fn default() -> Self {
+        Self {
+            desired_force: Vec2::ZERO,
+            desired_torque: 0.0,
+            throttle_mode: ThrottleMode::default(),
+            allocation_mode: AllocationMode::default(),
+            command_mode: CommandMode::default(),
+            allow_boost: false,
+            last_seen_center_of_mass: Vec2::ZERO,
+            last_seen_allow_boost: false,
+            last_seen_allocation_mode: AllocationMode::default(),
+            solver_config: ThrusterSolverConfig::default(),
+            thrust_scale: 1.0,
+            allocator: Arc::new(LinearProgramAllocator),
+            firings_cache: HashMap::new(),
+            firings_cache_clock: 0,
+            engines: None,
+            currently_firing: HashSet::new(),
+            engine_states: HashMap::new(),
+            dry_engines: HashSet::new(),
+            propellant_mass: MassProperties::new(Point::origin(), 0.0, 0.0),
+        }
+    }
 }
 
 impl Steering {
+    /// Creates a `Steering` which maps desires onto engines with `allocator` instead of
+    /// the default `LinearProgramAllocator`.
+    pub fn with_allocator(allocator: impl ThrustAllocator) -> Self {
+        Self {
+            allocator: Arc::new(allocator),
+            ..Default::default()
+        }
+    }
+
     pub fn clear_desire(&mut self) {
         self.desired_force = Vec2::splat(0.0);
         self.desired_torque = 0.0;
     }
 
+    /// The solver weights this ship's firings are computed with. They are taken from the
+    /// ship's `ThrusterSolverConfig` component, or from the resource if it has none, so insert
+    /// the component to change them for one ship.
+    pub fn solver_config(&self) -> &ThrusterSolverConfig {
+        &self.solver_config
+    }
+
+    /// Sets the solver weights for this ship, clearing cached firings if they changed.
+    pub(crate) fn set_solver_config(&mut self, config: ThrusterSolverConfig) {
+        if self.solver_config != config {
+            self.solver_config = config;
+            self.firings_cache.clear();
+        }
+    }
+
+    /// The `ThrustScale` this ship's engines are fired with, from its component or from the
+    /// resource if it has none. It is picked up at the start of each frame, before the
+    /// autopilots plan with it.
+    pub fn thrust_scale(&self) -> f32 {
+        self.thrust_scale
+    }
+
+    /// Replaces the allocator used to map this ship's desires onto its engines.
+    pub fn set_allocator(&mut self, allocator: impl ThrustAllocator) {
+        self.set_shared_allocator(Arc::new(allocator));
+    }
+
+    /// Like `set_allocator` but lets many ships share a single allocator.
+    pub fn set_shared_allocator(&mut self, allocator: Arc<dyn ThrustAllocator>) {
+        self.allocator = allocator;
+        self.firings_cache.clear();
+    }
+
+    /// What an engine is physically doing right now, if it has ever been commanded.
+    pub fn engine_state(&self, engine: (Entity, usize)) -> Option<&EngineState> {
+        self.engine_states.get(&engine)
+    }
+
+    /// Gives an engine its full boost budget back.
+    pub fn refill_boost(&mut self, engine: (Entity, usize)) {
+        if let Some(state) = self.engine_states.get_mut(&engine) {
+            if state.boost_used > 0.0 {
+                state.boost_used = 0.0;
+                self.engines = None;
+                self.firings_cache.clear();
+            }
+        }
+    }
+
+    /// True if any engine is still producing thrust, for example while spooling down.
+    pub fn is_firing(&self) -> bool {
+        self.engine_states
+            .values()
+            .any(|state| state.throttle > 0.0)
+    }
+
+    /// True if any engine's state still needs updating even without a desire, because it is
+    /// firing or cooling down.
+    fn is_active(&self) -> bool {
+        self.engine_states
+            .values()
+            .any(|state| state.throttle > 0.0 || state.heat > 0.0)
+    }
+
+    /// The boost the allocator can use for an engine, if any.
+    fn available_boost(&self, engine: &Engine, id: (Entity, usize)) -> Option<Boost> {
+        let boost = engine.boost.filter(|_| self.allow_boost)?;
+        let used = self
+            .engine_states
+            .get(&id)
+            .map_or(0.0, |state| state.boost_used);
+        (used < boost.duration).then_some(boost)
+    }
+
+    /// How much thrust the allocator can get out of an engine, given its damage, fuel, heat
+    /// and boost.
+    fn available_thrust(&self, engine: &Engine, id: (Entity, usize)) -> f32 {
+        if self.dry_engines.contains(&id) {
+            return 0.0;
+        }
+        let overheated = self
+            .engine_states
+            .get(&id)
+            .is_some_and(|state| state.overheated);
+        let boost = self
+            .available_boost(engine, id)
+            .map_or(1.0, |boost| boost.thrust_multiplier);
+        match engine.heat {
+            Some(heat) if overheated => {
+                engine.effective_max_thrust() * boost * heat.overheated_thrust
+            }
+            _ => engine.effective_max_thrust() * boost,
+        }
+    }
+
+    /// The engines this ship's allocator is working with, if the cache has been built.
+    pub fn engines(&self) -> Option<&[ResolvedEngine]> {
+        self.engines.as_deref()
+    }
+
     pub fn update_engine_cache(
         &mut self,
         parent: Entity,
@@ -107,34 +620,102 @@ impl Steering {
                     *transform
                 };
                 for (i, engine) in engine_set.0.iter().enumerate() {
-                    engines.push((
-                        (transform.translation.truncate() + engine.offset) / rapier_scale,
-                        transform
-                            .rotation
-                            .mul_vec3(engine.thrust_vector.extend(0.0))
-                            .truncate()
-                            .normalize(),
-                        engine.max_thrust,
-                        (e, i),
-                    ));
+                    let engine_direction = transform
+                        .rotation
+                        .mul_vec3(engine.thrust_vector.extend(0.0))
+                        .truncate()
+                        .normalize();
+                    let position =
+                        (transform.translation.truncate() + engine.offset) / rapier_scale;
+                    // A gimbaled engine is presented to the allocator as a fan of fixed
+                    // engines spread across its range which share an id.
+                    for deflection in allocator::gimbal_samples(engine.gimbal) {
+                        engines.push(ResolvedEngine {
+                            position,
+                            thrust_vector: Mat2::from_angle(deflection) * engine_direction,
+                            // Engines which can't fire are still resolved, so that their
+                            // commands stop them, but the allocator can't get any thrust out
+                            // of them.
+                            max_thrust: self.available_thrust(engine, (e, i)),
+                            id: (e, i),
+                            gimbal: engine.gimbal,
+                            deflection,
+                            min_throttle: engine.min_throttle,
+                            discrete: engine.discrete,
+                            spool_up_time: engine.spool_up_time,
+                            spool_down_time: engine.spool_down_time,
+                            fuel_consumption: engine.fuel_consumption,
+                            heat: engine.heat,
+                            boost: self.available_boost(engine, (e, i)),
+                        });
+                    }
                 }
             }
         }
         self.engines = Some(engines);
     }
 
-    pub fn estimate_acceleration(
-        &mut self,
+    /// Marks an engine as having run out of fuel, or as having fuel again, and returns true if
+    /// that changed. The engine cache has to be rebuilt afterwards.
+    fn set_engine_dry(&mut self, engine: (Entity, usize), dry: bool) -> bool {
+        let changed = if dry {
+            self.dry_engines.insert(engine)
+        } else {
+            self.dry_engines.remove(&engine)
+        };
+        if changed {
+            self.engines = None;
+            self.firings_cache.clear();
+        }
+        changed
+    }
+
+    /// The desires converted to the body frame and normalized the way allocators expect,
+    /// according to `command_mode`.
+    fn normalized_desire(
+        &self,
+        engines: &[ResolvedEngine],
         body: &RigidBody,
-        engine_scale: f32,
-    ) -> Option<(Vec2, f32)> {
-        let key = (
-            (self.desired_force.x / CACHE_COARSENESS) as i32,
-            (self.desired_force.y / CACHE_COARSENESS) as i32,
-            (self.desired_torque / CACHE_COARSENESS) as i32,
-        );
-        let center_of_mass = body.mass_properties().local_com;
-        let center_of_mass = Vec2::new(center_of_mass.x, center_of_mass.y);
+        center_of_mass: Vec2,
+    ) -> (Vec2, f32) {
+        let world_to_body = Mat2::from_angle(-body.position().rotation.angle());
+        let (force, torque) = match self.command_mode {
+            CommandMode::BodyNormalized => return (self.desired_force, self.desired_torque),
+            CommandMode::WorldNormalized => {
+                return (world_to_body * self.desired_force, self.desired_torque)
+            }
+            CommandMode::Force => (self.desired_force, self.desired_torque),
+            CommandMode::Acceleration => {
+                let inv_inertia_sqrt = body.effective_world_inv_inertia_sqrt;
+                (
+                    self.desired_force / body.effective_inv_mass,
+                    self.desired_torque / (inv_inertia_sqrt * inv_inertia_sqrt),
+                )
+            }
+        };
+        let (total_thrust, positive_torque, negative_torque) =
+            allocator::thrust_totals(engines, center_of_mass);
+        let force = world_to_body * force;
+        let max_torque = if torque > 0.0 {
+            positive_torque
+        } else {
+            negative_torque
+        };
+        (
+            Vec2::new(
+                normalize(force.x, total_thrust * self.thrust_scale),
+                normalize(force.y, total_thrust * self.thrust_scale),
+            ),
+            normalize(torque, max_torque * self.thrust_scale),
+        )
+    }
+
+    /// Looks up the firing for the current desires, asking the allocator for it if it isn't
+    /// cached. Returns `None` if the engine cache hasn't been built. The flag is true if the
+    /// firing was freshly computed rather than taken from the cache.
+    fn cached_firing(&mut self, body: &RigidBody) -> Option<(&[ResolvedEngine], &Firing, bool)> {
+        let center_of_mass = body_center_of_mass(body);
+        // TODO: This epsilon needs to depend on rapier scale? Or maybe be user configurable?
         if self
             .last_seen_center_of_mass
             .distance_squared(center_of_mass)
@@ -143,60 +724,266 @@ impl Steering {
             self.last_seen_center_of_mass = center_of_mass;
             self.firings_cache.clear();
         }
+        if self.last_seen_allocation_mode != self.allocation_mode {
+            self.last_seen_allocation_mode = self.allocation_mode;
+            self.firings_cache.clear();
+        }
+        let (desired_force, desired_torque) =
+            self.normalized_desire(self.engines.as_ref()?, body, center_of_mass);
+        let key = (
+            (desired_force.x / CACHE_COARSENESS) as i32,
+            (desired_force.y / CACHE_COARSENESS) as i32,
+            (desired_torque / CACHE_COARSENESS) as i32,
+        );
+        self.firings_cache_clock += 1;
+        let clock = self.firings_cache_clock;
+        if self.firings_cache.len() >= FIRINGS_CACHE_CAPACITY
+            && !self.firings_cache.contains_key(&key)
+        {
+            let oldest = self
+                .firings_cache
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.firings_cache.remove(&oldest);
+            }
+        }
         let Steering {
             ref engines,
             ref mut firings_cache,
-            desired_force,
-            desired_torque,
+            allocation_mode,
+            ref solver_config,
+            ref allocator,
             ..
         } = self;
-        if !firings_cache.contains_key(&key) {
-            if let Some(engines) = engines.as_ref() {
-                firings_cache.insert(
-                    key,
-                    optimizer::calculate_firing(
+        let engines = engines.as_ref()?;
+        Some(match firings_cache.entry(key) {
+            Entry::Occupied(entry) => {
+                let (firing, last_used) = entry.into_mut();
+                *last_used = clock;
+                (engines.as_slice(), &*firing, false)
+            }
+            Entry::Vacant(entry) => {
+                let firing = allocator::validate_inputs(
+                    engines,
+                    center_of_mass,
+                    desired_force,
+                    desired_torque,
+                )
+                .and_then(|_| {
+                    allocator.allocate(
                         engines,
                         center_of_mass,
-                        *desired_force,
-                        *desired_torque,
-                    ),
-                );
-            } else {
-                return None;
+                        desired_force,
+                        desired_torque,
+                        *allocation_mode,
+                        solver_config,
+                    )
+                });
+                (engines.as_slice(), &entry.insert((firing, clock)).0, true)
+            }
+        })
+    }
+
+    /// Estimates the acceleration the engines would produce if they instantly reached the
+    /// thrust the allocator wants for the current desires, using the ship's `ThrustScale`.
+    pub fn estimate_acceleration(&mut self, body: &RigidBody) -> Option<(Vec2, f32)> {
+        self.estimate(body, None)
+    }
+
+    /// Like `estimate_acceleration` but accounts for spool times and gimbal slew rates,
+    /// estimating the acceleration the engines will actually produce `lookahead` seconds from
+    /// now if the current desires are held.
+    pub fn estimate_lagged_acceleration(
+        &mut self,
+        body: &RigidBody,
+        lookahead: f32,
+    ) -> Option<(Vec2, f32)> {
+        self.estimate(body, Some(lookahead))
+    }
+
+    /// Estimates the acceleration the engines would produce for hypothetical desires, given
+    /// in the `BodyNormalized` command mode, without touching the current ones. Useful for
+    /// finding out how much authority the ship has in a direction. The linear acceleration is
+    /// in the ship's frame.
+    pub fn estimate_acceleration_for(
+        &mut self,
+        body: &RigidBody,
+        desired_force: Vec2,
+        desired_torque: f32,
+    ) -> Option<(Vec2, f32)> {
+        let saved = (self.desired_force, self.desired_torque, self.command_mode);
+        self.desired_force = desired_force;
+        self.desired_torque = desired_torque;
+        self.command_mode = CommandMode::BodyNormalized;
+        let estimate = self.estimate(body, None);
+        self.desired_force = saved.0;
+        self.desired_torque = saved.1;
+        self.command_mode = saved.2;
+        estimate
+    }
+
+    /// Sets `desired_force` to ask for a linear acceleration in the world frame, converting it
+    /// to the ship's `command_mode`.
+    pub fn request_linear_acceleration(&mut self, body: &RigidBody, acceleration: Vec2) {
+        let force = acceleration / body.effective_inv_mass;
+        let body_to_world = Mat2::from_angle(body.position().rotation.angle());
+        let total_thrust = self.engines.as_deref().map_or(0.0, |engines| {
+            allocator::thrust_totals(engines, body_center_of_mass(body)).0
+        }) * self.thrust_scale;
+        self.desired_force = match self.command_mode {
+            CommandMode::BodyNormalized => {
+                let force = body_to_world.transpose() * force;
+                Vec2::new(
+                    normalize(force.x, total_thrust),
+                    normalize(force.y, total_thrust),
+                )
+            }
+            CommandMode::WorldNormalized => Vec2::new(
+                normalize(force.x, total_thrust),
+                normalize(force.y, total_thrust),
+            ),
+            CommandMode::Force => force,
+            CommandMode::Acceleration => acceleration,
+        };
+    }
+
+    /// Sets `desired_torque` to ask for an angular acceleration, converting it to the ship's
+    /// `command_mode`.
+    pub fn request_angular_acceleration(&mut self, body: &RigidBody, angular_acceleration: f32) {
+        let inv_inertia_sqrt = body.effective_world_inv_inertia_sqrt;
+        let torque = angular_acceleration / (inv_inertia_sqrt * inv_inertia_sqrt);
+        self.desired_torque = match self.command_mode {
+            CommandMode::BodyNormalized | CommandMode::WorldNormalized => {
+                let (_, positive_torque, negative_torque) =
+                    self.engines.as_deref().map_or((0.0, 0.0, 0.0), |engines| {
+                        allocator::thrust_totals(engines, body_center_of_mass(body))
+                    });
+                let max_torque = if torque > 0.0 {
+                    positive_torque
+                } else {
+                    negative_torque
+                };
+                normalize(torque, max_torque * self.thrust_scale)
+            }
+            CommandMode::Force => torque,
+            CommandMode::Acceleration => angular_acceleration,
+        };
+    }
+
+    fn estimate(&mut self, body: &RigidBody, lookahead: Option<f32>) -> Option<(Vec2, f32)> {
+        let center_of_mass = body_center_of_mass(body);
+        let throttle_mode = self.throttle_mode;
+        let (engines, firing, _) = self.cached_firing(body)?;
+        let mut commands =
+            allocator::engine_commands(engines, firing.as_ref().ok()?, throttle_mode);
+        if let Some(lookahead) = lookahead {
+            for command in &mut commands {
+                let mut state = self
+                    .engine_states
+                    .get(&command.id)
+                    .copied()
+                    .unwrap_or_default();
+                *command = state.step(command, lookahead);
             }
         }
-        let firing = firings_cache.get(&key).unwrap();
         Some(optimizer::estimate_acceleration(
             body.effective_world_inv_inertia_sqrt,
             body.effective_inv_mass,
-            engine_scale,
+            self.thrust_scale,
             center_of_mass,
-            self.engines.as_ref().unwrap(),
-            &firing,
+            &commands,
         ))
     }
 }
 
-fn fire_engines(
+/// Copies each ship's effective `ThrustScale` and `ThrusterSolverConfig` into its `Steering`,
+/// only touching it when they changed.
+#[allow(clippy::type_complexity)]
+fn apply_ship_settings(
     thrust_scale: Res<ThrustScale>,
+    solver_config: Res<ThrusterSolverConfig>,
+    mut ship_query: Query<(
+        &mut Steering,
+        Option<&ThrustScale>,
+        Option<&ThrusterSolverConfig>,
+    )>,
+) {
+    for (mut steering, maybe_thrust_scale, maybe_solver_config) in ship_query.iter_mut() {
+        let scale = maybe_thrust_scale.unwrap_or(&*thrust_scale).0;
+        if steering.thrust_scale != scale {
+            steering.thrust_scale = scale;
+        }
+        let config = *maybe_solver_config.unwrap_or(&*solver_config);
+        if steering.solver_config != config {
+            steering.set_solver_config(config);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn fire_engines(
+    time: Res<Time>,
     rapier_config: Res<RapierConfiguration>,
     mut body_set: ResMut<RigidBodySet>,
     mut engine_events: ResMut<Events<EngineEvent>>,
+    mut error_events: ResMut<Events<ThrusterError>>,
     mut parent_query: Query<(
         Entity,
-        &mut GlobalTransform,
+        &GlobalTransform,
         &mut Steering,
         &RigidBodyHandleComponent,
         Option<&Children>,
+        Option<&Plumbing>,
     )>,
     engine_query: Query<(&Transform, &EngineSet)>,
+    mut tank_query: Query<&mut FuelTank>,
 ) {
-    for (parent, mut parent_transform, mut steering, body_handle, maybe_children) in
+    for (parent, parent_transform, mut steering, body_handle, maybe_children, maybe_plumbing) in
         parent_query.iter_mut()
     {
         let mut just_fired = Vec::with_capacity(steering.currently_firing.len());
-        if steering.desired_force != Vec2::splat(0.0) || steering.desired_torque != 0.0 {
+        if steering.last_seen_allow_boost != steering.allow_boost {
+            steering.last_seen_allow_boost = steering.allow_boost;
+            steering.engines = None;
+            steering.firings_cache.clear();
+        }
+        // The cache is built even for idle ships so that autopilots can plan with it.
+        if steering.engines.is_none() {
+            steering.update_engine_cache(
+                parent,
+                rapier_config.scale,
+                maybe_children,
+                &engine_query,
+            );
+        }
+        let has_desire =
+            steering.desired_force != Vec2::splat(0.0) || steering.desired_torque != 0.0;
+        if has_desire || steering.is_active() {
             if let Some(body) = body_set.get_mut(body_handle.handle()) {
+                // Engines with nothing left to burn are taken away from the allocator.
+                let mut fueled: Vec<(Entity, usize)> = steering
+                    .engines
+                    .as_deref()
+                    .unwrap()
+                    .iter()
+                    .filter(|e| e.fuel_consumption > 0.0)
+                    .map(|e| e.id)
+                    .collect();
+                fueled.dedup();
+                for id in fueled {
+                    let tanks = fuel::engine_tanks(parent, maybe_children, maybe_plumbing, id);
+                    let dry = fuel::available_fuel(
+                        &tank_query,
+                        &tanks,
+                        &engine_mixture(&engine_query, id),
+                        0.0,
+                    ) <= 0.0;
+                    if steering.set_engine_dry(id, dry) && dry {
+                        engine_events.send(EngineEvent::OutOfFuel(id.0, id.1));
+                    }
+                }
                 if steering.engines.is_none() {
                     steering.update_engine_cache(
                         parent,
@@ -206,55 +993,110 @@ fn fire_engines(
                     );
                 }
 
-                let center_of_mass = body.mass_properties().local_com;
-                let center_of_mass = Vec2::new(center_of_mass.x, center_of_mass.y);
-                // TODO: This epsilon needs to depend on rapier scale? Or maybe be user configurable?
-                if steering
-                    .last_seen_center_of_mass
-                    .distance_squared(center_of_mass)
-                    > 0.5
-                {
-                    steering.last_seen_center_of_mass = center_of_mass;
-                    steering.firings_cache.clear();
-                }
-
-                let key = (
-                    (steering.desired_force.x / CACHE_COARSENESS) as i32,
-                    (steering.desired_force.y / CACHE_COARSENESS) as i32,
-                    (steering.desired_torque / CACHE_COARSENESS) as i32,
-                );
-
-                let Steering {
-                    ref engines,
-                    ref mut firings_cache,
-                    desired_force,
-                    desired_torque,
-                    ..
-                } = &mut *steering;
-                let firing = firings_cache.entry(key).or_insert_with(|| {
-                    optimizer::calculate_firing(
-                        engines.as_ref().unwrap(),
-                        center_of_mass,
-                        *desired_force,
-                        *desired_torque,
+                let throttle_mode = steering.throttle_mode;
+                let commands = if has_desire {
+                    let (engines, firing, fresh) = steering.cached_firing(body).unwrap();
+                    if let (Err(error), true) = (firing, fresh) {
+                        error_events.send(ThrusterError::SolveFailed(parent, *error));
+                    }
+                    // If the optimizer failed we command nothing, which also stops any
+                    // engines that were already running.
+                    let firing: &[f32] = firing.as_deref().unwrap_or(&[]);
+                    allocator::engine_commands(engines, firing, throttle_mode)
+                } else {
+                    // No desire, but some engines are still spooling down.
+                    allocator::engine_commands(
+                        steering.engines.as_deref().unwrap(),
+                        &[],
+                        throttle_mode,
                     )
-                });
+                };
 
-                for ((position, thrust_vector, max_thrust, event_key), firing) in
-                    engines.as_ref().unwrap().iter().zip(firing)
-                {
-                    if *firing > 0.0 {
-                        just_fired.push((event_key.0, event_key.1, *firing));
-                        parent_transform.translation /= rapier_config.scale;
-                        let p = parent_transform.mul_vec3(position.extend(0.0));
+                let mut scaled_transform = *parent_transform;
+                scaled_transform.translation /= rapier_config.scale;
+                let steering = &mut *steering;
+                for command in commands {
+                    let state = steering.engine_states.entry(command.id).or_default();
+                    let previous = *state;
+                    let mut actual = state.step(&command, time.delta_seconds());
+                    if actual.throttle > 0.0 && actual.fuel_consumption > 0.0 {
+                        let tanks =
+                            fuel::engine_tanks(parent, maybe_children, maybe_plumbing, command.id);
+                        let available = fuel::draw_fuel(
+                            &mut tank_query,
+                            &tanks,
+                            &engine_mixture(&engine_query, command.id),
+                            actual.fuel_consumption * actual.load() * time.delta_seconds(),
+                        );
+                        if available < 1.0 {
+                            // The engine flames out and has to spool back up once there is
+                            // fuel again.
+                            actual.throttle *= available;
+                            state.throttle = actual.throttle;
+                        }
+                    }
+                    if let Some(heat) = actual.heat {
+                        if state.update_heat(&heat, actual.load(), time.delta_seconds()) {
+                            engine_events.send(if state.overheated {
+                                EngineEvent::Overheated(command.id.0, command.id.1)
+                            } else {
+                                EngineEvent::CooledDown(command.id.0, command.id.1)
+                            });
+                            // The allocator has to see the engine's new limit.
+                            steering.engines = None;
+                            steering.firings_cache.clear();
+                        }
+                    }
+                    if let Some(boost) = actual.boost {
+                        let usage = actual.boost_usage();
+                        if usage > 0.0 && !state.boosting {
+                            engine_events
+                                .send(EngineEvent::BoostStarted(command.id.0, command.id.1));
+                        } else if usage <= 0.0 && state.boosting {
+                            engine_events.send(EngineEvent::BoostStopped(
+                                command.id.0,
+                                command.id.1,
+                                boost.duration - state.boost_used,
+                            ));
+                        }
+                        if state.update_boost(&boost, usage, time.delta_seconds()) {
+                            engine_events
+                                .send(EngineEvent::BoostExhausted(command.id.0, command.id.1));
+                            steering.engines = None;
+                            steering.firings_cache.clear();
+                        }
+                    }
+                    if state.gimbal_angle != previous.gimbal_angle {
+                        engine_events.send(EngineEvent::Gimbaled(
+                            command.id.0,
+                            command.id.1,
+                            state.gimbal_angle,
+                        ));
+                    }
+                    if actual.throttle > 0.0 {
+                        // Only new commands are reported, not every frame of spooling.
+                        if previous.throttle > 0.0
+                            && command.throttle != previous.commanded_throttle
+                        {
+                            engine_events.send(EngineEvent::ThrottleChanged(
+                                command.id.0,
+                                command.id.1,
+                                command.throttle,
+                            ));
+                        }
+                        just_fired.push((command.id.0, command.id.1, actual.throttle));
+                        let p = scaled_transform.mul_vec3(actual.position.extend(0.0));
                         let p = Point::new(p.x, p.y);
-                        let thrust_vector = parent_transform
+                        let thrust_vector = scaled_transform
                             .rotation
-                            .mul_vec3(thrust_vector.extend(0.0));
+                            .mul_vec3(actual.thrust_vector.extend(0.0));
                         let thrust_vector =
                             Vector::new(thrust_vector.x, thrust_vector.y).normalize();
                         body.apply_force_at_point(
-                            thrust_vector * *max_thrust * thrust_scale.0,
+                            thrust_vector
+                                * actual.max_thrust
+                                * steering.thrust_scale
+                                * actual.throttle,
                             p,
                             true,
                         );
@@ -277,6 +1119,63 @@ fn fire_engines(
     }
 }
 
+/// The propellants an engine burns, looked up from its `EngineSet`.
+fn engine_mixture(
+    engine_query: &Query<(&Transform, &EngineSet)>,
+    engine: (Entity, usize),
+) -> Mixture {
+    engine_query
+        .get(engine.0)
+        .ok()
+        .and_then(|(_, engine_set)| engine_set.0.get(engine.1))
+        .map_or(Mixture::default(), |engine| engine.propellants)
+}
+
+fn validate_engines(
+    mut error_events: EventWriter<ThrusterError>,
+    engine_query: Query<(Entity, &EngineSet), Changed<EngineSet>>,
+) {
+    for (entity, engine_set) in engine_query.iter() {
+        for (i, engine) in engine_set.0.iter().enumerate() {
+            if let Err(error) = engine.validate() {
+                error_events.send(ThrusterError::InvalidEngine(entity, i, error));
+            }
+        }
+    }
+}
+
+fn damage_engines(
+    mut damage_events: EventReader<EngineDamage>,
+    mut engine_events: EventWriter<EngineEvent>,
+    mut engine_query: Query<&mut EngineSet>,
+) {
+    for damage in damage_events.iter() {
+        let (entity, index) = damage.engine();
+        if let Ok(mut engine_set) = engine_query.get_mut(entity) {
+            // Only borrow the set mutably once there is something to change, so damage to a
+            // destroyed engine doesn't mark it changed and invalidate the steering cache.
+            if engine_set
+                .0
+                .get(index)
+                .map_or(true, |engine| engine.disabled)
+            {
+                continue;
+            }
+            let engine = &mut engine_set.0[index];
+            if let EngineDamage::Damage(_, _, amount) = damage {
+                engine.health = (engine.health - amount).clamp(0.0, 1.0);
+            }
+            if engine.health > 0.0 && matches!(damage, EngineDamage::Damage(..)) {
+                engine_events.send(EngineEvent::Damaged(entity, index, engine.health));
+            } else {
+                engine.health = 0.0;
+                engine.disabled = true;
+                engine_events.send(EngineEvent::Destroyed(entity, index));
+            }
+        }
+    }
+}
+
 fn invalidate_caches(
     parent_engines: Query<Entity, (Changed<EngineSet>, With<Steering>)>,
     child_engines: Query<&Parent, (Changed<EngineSet>, Without<Steering>)>,
@@ -297,16 +1196,438 @@ fn invalidate_caches(
     }
 }
 
+/// Changes in what engines are physically doing. Throttles are the thrust the engine is
+/// actually producing, as a fraction of its max thrust, which lags the allocator's command
+/// while the engine spools.
 #[derive(Debug)]
 pub enum EngineEvent {
     StartedFiring(Entity, usize, f32),
     StoppedFiring(Entity, usize),
+    /// The throttle commanded for a firing engine changed to the given value. It isn't sent
+    /// while an engine spools toward its command, `Steering::engine_state` has the throttle
+    /// the engine is actually at.
+    ThrottleChanged(Entity, usize, f32),
+    /// Every tank holding one of an engine's propellants has run dry. The engine won't be
+    /// used again until one of them is refilled.
+    OutOfFuel(Entity, usize),
+    /// A gimbaled engine's deflection changed. The angle is in radians, relative to the
+    /// engine's `thrust_vector`.
+    Gimbaled(Entity, usize, f32),
+    /// An engine was damaged and now has the given health.
+    Damaged(Entity, usize, f32),
+    /// An engine was destroyed and won't fire again.
+    Destroyed(Entity, usize),
+    /// An engine got too hot and is derated until it cools down.
+    Overheated(Entity, usize),
+    /// An overheated engine cooled down and is back to full thrust.
+    CooledDown(Entity, usize),
+    /// An engine started running above its unboosted rating.
+    BoostStarted(Entity, usize),
+    /// An engine dropped back to its unboosted rating. Reports the seconds of full boost it
+    /// has left.
+    BoostStopped(Entity, usize, f32),
+    /// An engine used up its boost budget and is back to its unboosted rating.
+    BoostExhausted(Entity, usize),
 }
 
 impl EngineEvent {
     pub fn engine(&self) -> (Entity, usize) {
         match self {
-            EngineEvent::StartedFiring(e, i, ..) | EngineEvent::StoppedFiring(e, i, ..) => (*e, *i),
+            EngineEvent::StartedFiring(e, i, ..)
+            | EngineEvent::StoppedFiring(e, i, ..)
+            | EngineEvent::ThrottleChanged(e, i, ..)
+            | EngineEvent::OutOfFuel(e, i)
+            | EngineEvent::Gimbaled(e, i, ..)
+            | EngineEvent::Damaged(e, i, ..)
+            | EngineEvent::Destroyed(e, i)
+            | EngineEvent::Overheated(e, i)
+            | EngineEvent::CooledDown(e, i)
+            | EngineEvent::BoostStarted(e, i)
+            | EngineEvent::BoostStopped(e, i, ..)
+            | EngineEvent::BoostExhausted(e, i) => (*e, *i),
         }
     }
 }
+
+/// Send these to damage engines at runtime. Damage is applied at the end of the frame and the
+/// ship's allocator works around the damaged engines from the next one on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EngineDamage {
+    /// Takes the given amount off an engine's health, destroying it if none is left.
+    Damage(Entity, usize, f32),
+    /// Destroys an engine outright.
+    Destroy(Entity, usize),
+}
+
+impl EngineDamage {
+    pub fn engine(&self) -> (Entity, usize) {
+        match self {
+            EngineDamage::Damage(e, i, ..) | EngineDamage::Destroy(e, i) => (*e, *i),
+        }
+    }
+}
+
+/// Reasons an `Engine` can't be used by the optimizer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EngineError {
+    NonFiniteOffset,
+    InvalidThrustVector,
+    InvalidMaxThrust,
+    InvalidGimbal,
+    InvalidMinThrottle,
+    InvalidSpoolTime,
+    InvalidFuelConsumption,
+    InvalidMixture,
+    InvalidHealth,
+    InvalidHeat,
+    InvalidBoost,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::NonFiniteOffset => write!(f, "engine offset is not finite"),
+            EngineError::InvalidThrustVector => {
+                write!(f, "engine thrust vector is zero length or not finite")
+            }
+            EngineError::InvalidMaxThrust => {
+                write!(f, "engine max thrust is negative or not finite")
+            }
+            EngineError::InvalidGimbal => write!(
+                f,
+                "engine gimbal range is outside of 0 to PI or slew rate isn't positive"
+            ),
+            EngineError::InvalidMinThrottle => {
+                write!(f, "engine min throttle is outside of 0 to 1")
+            }
+            EngineError::InvalidSpoolTime => {
+                write!(f, "engine spool time is negative or not finite")
+            }
+            EngineError::InvalidFuelConsumption => {
+                write!(f, "engine fuel consumption is negative or not finite")
+            }
+            EngineError::InvalidMixture => {
+                write!(f, "engine propellant ratio is not positive and finite")
+            }
+            EngineError::InvalidHealth => write!(f, "engine health is outside of 0 to 1"),
+            EngineError::InvalidHeat => write!(f, "engine heat model is invalid"),
+            EngineError::InvalidBoost => write!(f, "engine boost is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// Reasons the optimizer can fail to find a firing for a ship.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SolveError {
+    /// The engine's position, direction or max thrust is not usable.
+    InvalidEngine(Entity, usize),
+    /// The desired force or torque, or the ship's center of mass, is not finite.
+    InvalidDesire,
+    Infeasible,
+    Unbounded,
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::InvalidEngine(e, i) => write!(f, "engine {} on {:?} is invalid", i, e),
+            SolveError::InvalidDesire => {
+                write!(f, "desired force, torque or center of mass is not finite")
+            }
+            SolveError::Infeasible => write!(f, "engine allocation is infeasible"),
+            SolveError::Unbounded => write!(f, "engine allocation is unbounded"),
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+#[derive(Debug)]
+pub enum ThrusterError {
+    /// An engine failed validation when its `EngineSet` was added or changed.
+    InvalidEngine(Entity, usize, EngineError),
+    /// The optimizer couldn't find a firing for the ship, so none of its engines fired.
+    SolveFailed(Entity, SolveError),
+    /// The ship's `Plumbing` refers to a tank which isn't on the ship entity or one of its
+    /// children. The tank is ignored.
+    ForeignTank(Entity, Entity),
+}
+
+impl ThrusterError {
+    pub fn entity(&self) -> Entity {
+        match self {
+            ThrusterError::InvalidEngine(e, ..)
+            | ThrusterError::SolveFailed(e, ..)
+            | ThrusterError::ForeignTank(e, ..) => *e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::ManualEventReader;
+    use bevy_rapier2d::{
+        physics::RapierPhysicsPlugin,
+        rapier::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder},
+    };
+
+    use super::*;
+
+    #[test]
+    fn firings_cache_is_bounded_and_keeps_recent_firings() {
+        let body = RigidBodyBuilder::new_dynamic().build();
+        let mut steering = Steering {
+            engines: Some(allocator::test_support::ship()),
+            ..Default::default()
+        };
+        let hot = Vec2::new(0.0, 0.2);
+        for i in 0..4 * FIRINGS_CACHE_CAPACITY {
+            steering.desired_force = Vec2::new(i as f32 * 0.01, 0.1);
+            assert!(steering.cached_firing(&body).unwrap().2);
+            steering.desired_force = hot;
+            let fresh = steering.cached_firing(&body).unwrap().2;
+            assert_eq!(fresh, i == 0);
+            assert!(steering.firings_cache.len() <= FIRINGS_CACHE_CAPACITY);
+        }
+    }
+
+    #[test]
+    fn ship_thrust_scale_overrides_the_resource_before_the_first_firing() {
+        // What the autopilots would see, recorded just before the engines are fired.
+        #[derive(Default)]
+        struct Seen(Vec<f32>);
+        let record = |ships: Query<&Steering>, mut seen: ResMut<Seen>| {
+            seen.0 = ships.iter().map(Steering::thrust_scale).collect();
+        };
+
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(RapierPhysicsPlugin)
+            .add_plugin(ThrusterPlugin)
+            .insert_resource(ThrustScale(3.0))
+            .init_resource::<Seen>()
+            .add_system(record.system().before(SystemLabels::FireEngines));
+        let own = builder
+            .world_mut()
+            .spawn()
+            .insert_bundle((Steering::default(), ThrustScale(200000.0)))
+            .id();
+        let shared = builder.world_mut().spawn().insert(Steering::default()).id();
+        let mut app = builder.app;
+        let scale = |app: &App, ship| app.world.get::<Steering>(ship).unwrap().thrust_scale();
+
+        app.update();
+        let mut seen = app.world.get_resource::<Seen>().unwrap().0.clone();
+        seen.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(seen, vec![3.0, 200000.0]);
+
+        app.world.get_resource_mut::<ThrustScale>().unwrap().0 = 5.0;
+        app.update();
+        assert_eq!(scale(&app, own), 200000.0);
+        assert_eq!(scale(&app, shared), 5.0);
+
+        app.world.entity_mut(own).remove::<ThrustScale>();
+        app.update();
+        assert_eq!(scale(&app, own), 5.0);
+    }
+
+    #[test]
+    fn command_modes_convert_desires_into_the_body_frame() {
+        let engines = allocator::test_support::ship();
+        let (total_thrust, positive_torque, _) = allocator::thrust_totals(&engines, Vec2::ZERO);
+        // Turned a quarter turn left, so the world's +x is the body's -y.
+        let mut body = RigidBodyBuilder::new_dynamic()
+            .rotation(std::f32::consts::FRAC_PI_2)
+            .build();
+        body.set_mass_properties(MassProperties::new(Point::origin(), 4.0, 8.0), false);
+        let mut steering = Steering {
+            desired_force: Vec2::new(1.0, 0.0),
+            desired_torque: 0.5,
+            thrust_scale: 10.0,
+            ..Default::default()
+        };
+        let mut desire = |mode| {
+            steering.command_mode = mode;
+            steering.normalized_desire(&engines, &body, Vec2::ZERO)
+        };
+        let assert_desire = |(force, torque): (Vec2, f32), expected: (Vec2, f32)| {
+            assert!(
+                force.distance(expected.0) < 1e-5,
+                "{} != {}",
+                force,
+                expected.0
+            );
+            assert!(
+                (torque - expected.1).abs() < 1e-5,
+                "{} != {}",
+                torque,
+                expected.1
+            );
+        };
+
+        let body_frame = Vec2::new(0.0, -1.0);
+        assert_desire(
+            desire(CommandMode::BodyNormalized),
+            (Vec2::new(1.0, 0.0), 0.5),
+        );
+        assert_desire(desire(CommandMode::WorldNormalized), (body_frame, 0.5));
+        assert_desire(
+            desire(CommandMode::Force),
+            (
+                body_frame / (total_thrust * 10.0),
+                0.5 / (positive_torque * 10.0),
+            ),
+        );
+        assert_desire(
+            desire(CommandMode::Acceleration),
+            (
+                body_frame * 4.0 / (total_thrust * 10.0),
+                0.5 * 8.0 / (positive_torque * 10.0),
+            ),
+        );
+    }
+
+    #[test]
+    fn damage_wears_engines_down_and_destroys_them_once() {
+        // Whether an `EngineSet` was marked changed in the last frame.
+        #[derive(Default)]
+        struct SetChanged(bool);
+        let record = |sets: Query<(), Changed<EngineSet>>, mut changed: ResMut<SetChanged>| {
+            changed.0 = sets.iter().next().is_some();
+        };
+
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(RapierPhysicsPlugin)
+            .add_plugin(ThrusterPlugin)
+            .init_resource::<SetChanged>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                record.system().after(SystemLabels::DamageEngines),
+            );
+        let ship = builder
+            .world_mut()
+            .spawn()
+            .insert(EngineSet(vec![Engine::default(), Engine::default()]))
+            .id();
+        let mut app = builder.app;
+        app.update();
+        let mut reader = ManualEventReader::<EngineEvent>::default();
+        let mut apply = |app: &mut App, damage: &[EngineDamage]| {
+            let mut events = app
+                .world
+                .get_resource_mut::<Events<EngineDamage>>()
+                .unwrap();
+            for damage in damage {
+                events.send(*damage);
+            }
+            app.update();
+            let events = app.world.get_resource::<Events<EngineEvent>>().unwrap();
+            let reported = reader
+                .iter(events)
+                .map(|event| match event {
+                    EngineEvent::Damaged(_, i, health) => format!("damaged {} {}", i, health),
+                    EngineEvent::Destroyed(_, i) => format!("destroyed {}", i),
+                    other => format!("{:?}", other),
+                })
+                .collect::<Vec<_>>();
+            (reported, app.world.get_resource::<SetChanged>().unwrap().0)
+        };
+        let engine = |app: &App, i: usize| app.world.get::<EngineSet>(ship).unwrap().0[i];
+
+        let (reported, changed) = apply(&mut app, &[EngineDamage::Damage(ship, 0, 0.25)]);
+        assert_eq!(reported, vec!["damaged 0 0.75"]);
+        assert!(changed);
+        assert_eq!(engine(&app, 0).health, 0.75);
+        assert!(!engine(&app, 0).disabled);
+
+        // Damage past the remaining health destroys the engine, as does `Destroy`.
+        let (reported, _) = apply(
+            &mut app,
+            &[
+                EngineDamage::Damage(ship, 0, 2.0),
+                EngineDamage::Destroy(ship, 1),
+            ],
+        );
+        assert_eq!(reported, vec!["destroyed 0", "destroyed 1"]);
+        for i in 0..2 {
+            assert_eq!(engine(&app, i).health, 0.0);
+            assert!(engine(&app, i).disabled);
+        }
+
+        // Destroyed engines, and engines that don't exist, take no more damage and don't
+        // mark the set changed.
+        let (reported, changed) = apply(
+            &mut app,
+            &[
+                EngineDamage::Damage(ship, 0, 0.5),
+                EngineDamage::Destroy(ship, 1),
+                EngineDamage::Destroy(ship, 2),
+            ],
+        );
+        assert!(reported.is_empty());
+        assert!(!changed);
+    }
+
+    #[test]
+    fn throttle_changes_are_reported_per_command_not_while_spooling() {
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(RapierPhysicsPlugin)
+            .add_plugin(ThrusterPlugin);
+        let ship = builder
+            .world_mut()
+            .spawn()
+            .insert_bundle((
+                RigidBodyBuilder::new_dynamic(),
+                ColliderBuilder::ball(1.0),
+                Transform::default(),
+                GlobalTransform::default(),
+                Steering {
+                    desired_force: Vec2::new(0.0, 0.5),
+                    ..Default::default()
+                },
+                EngineSet(vec![Engine {
+                    spool_up_time: 100.0,
+                    spool_down_time: 100.0,
+                    ..Default::default()
+                }]),
+            ))
+            .id();
+        let mut app = builder.app;
+        let mut reader = ManualEventReader::<EngineEvent>::default();
+        let mut frames = |app: &mut App, count| {
+            let mut reported = vec![];
+            for _ in 0..count {
+                app.update();
+                let events = app.world.get_resource::<Events<EngineEvent>>().unwrap();
+                reported.extend(reader.iter(events).map(|event| match event {
+                    EngineEvent::StartedFiring(..) => "started".to_string(),
+                    EngineEvent::ThrottleChanged(_, _, throttle) => format!("to {}", throttle),
+                    other => format!("{:?}", other),
+                }));
+            }
+            reported
+        };
+        let state = |app: &App| {
+            *app.world
+                .get::<Steering>(ship)
+                .unwrap()
+                .engine_state((ship, 0))
+                .unwrap()
+        };
+
+        assert_eq!(frames(&mut app, 10), vec!["started"]);
+        let spooling = state(&app);
+        assert!(spooling.throttle > 0.0 && spooling.throttle < 0.5);
+        assert_eq!(spooling.commanded_throttle, 0.5);
+
+        app.world.get_mut::<Steering>(ship).unwrap().desired_force = Vec2::new(0.0, 0.25);
+        assert_eq!(frames(&mut app, 10), vec!["to 0.25"]);
+    }
+}
@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use minilp::{ComparisonOp, Solution, Variable};
+
+use crate::{
+    allocator::engine_groups,
+    optimizer::{self, Objective},
+    AllocationMode, ResolvedEngine, SolveError, ThrustAllocator, ThrusterSolverConfig,
+};
+
+/// An allocator which respects `Engine::min_throttle` and `Engine::discrete` by adding an
+/// on/off variable for each such engine to the LP used by `LinearProgramAllocator` and
+/// solving it with branch-and-bound.
+///
+/// The search is depth first and stops after `max_nodes` LP relaxations, returning the best
+/// allocation found so far. Switching every engine off is always allowed, so there is always
+/// an answer. Prioritized allocation modes are approximated by weighting, the same way
+/// `QuadraticAllocator` does, and `AllocationMode::FuelOptimal` also costs each engine by its
+/// propellant flow.
+#[derive(Copy, Clone, Debug)]
+pub struct MixedIntegerAllocator {
+    pub max_nodes: usize,
+}
+
+impl Default for MixedIntegerAllocator {
+    fn default() -> Self {
+        Self { max_nodes: 256 }
+    }
+}
+
+impl ThrustAllocator for MixedIntegerAllocator {
+    fn allocate(
+        &self,
+        engines: &[ResolvedEngine],
+        center_of_mass: Vec2,
+        desired_force: Vec2,
+        desired_torque: f32,
+        mode: AllocationMode,
+        config: &ThrusterSolverConfig,
+    ) -> Result<Vec<f32>, SolveError> {
+        let config = mode.weight_config(config);
+        let objective = if mode == AllocationMode::FuelOptimal {
+            Objective::FUEL_WEIGHTED
+        } else {
+            Objective::BLENDED
+        };
+        let mut firing_problem = optimizer::build_problem(
+            engines,
+            center_of_mass,
+            desired_force,
+            desired_torque,
+            &config,
+            objective,
+        );
+
+        // Each engine which can't throttle freely gets a switch `z` with
+        // `min_throttle * z <= activation <= z`.
+        let mut switches: Vec<Variable> = vec![];
+        for (group, activations) in engine_groups(engines).zip(&firing_problem.engine_activations) {
+            let engine = &group[0];
+            let min_throttle = if engine.discrete {
+                1.0
+            } else {
+                engine.min_throttle as f64
+            };
+            if min_throttle <= 0.0 {
+                continue;
+            }
+            let z = firing_problem.problem.add_var(0.0, (0.0, 1.0));
+            let mut upper: Vec<_> = activations.iter().map(|a| (*a, 1.0)).collect();
+            upper.push((z, -1.0));
+            let mut lower = upper.clone();
+            lower.last_mut().unwrap().1 = -min_throttle;
+            firing_problem
+                .problem
+                .add_constraint(upper.as_slice(), ComparisonOp::Le, 0.0);
+            firing_problem
+                .problem
+                .add_constraint(lower.as_slice(), ComparisonOp::Ge, 0.0);
+            switches.push(z);
+        }
+
+        let root = firing_problem
+            .problem
+            .solve()
+            .map_err(optimizer::solve_error)?;
+        let mut best: Option<Solution> = None;
+        let mut stack = vec![root];
+        let mut nodes = 0;
+        while let Some(node) = stack.pop() {
+            nodes += 1;
+            if nodes > self.max_nodes {
+                break;
+            }
+            // The relaxation can only get worse further down, so prune anything that can't
+            // beat what we already have.
+            if let Some(best) = &best {
+                if node.objective() >= best.objective() - 1e-9 {
+                    continue;
+                }
+            }
+            let fractional = switches
+                .iter()
+                .map(|z| (*z, node[*z]))
+                .filter(|(_, value)| value.min(1.0 - value) > 1e-6)
+                .max_by(|(_, a), (_, b)| a.min(1.0 - a).total_cmp(&b.min(1.0 - b)));
+            match fractional {
+                None => best = Some(node),
+                Some((z, value)) => {
+                    // Push the branch nearest the relaxed value last so it is explored first.
+                    let branches = if value < 0.5 { [1.0, 0.0] } else { [0.0, 1.0] };
+                    for branch in branches {
+                        if let Ok(child) = node.clone().fix_var(z, branch) {
+                            stack.push(child);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(match best {
+            Some(solution) => firing_problem.activations(&solution),
+            None => vec![0.0; engines.len()],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::test_support::*;
+    use crate::LinearProgramAllocator;
+
+    #[test]
+    fn respects_min_throttle() {
+        let mut engines = ship();
+        let force = Vec2::new(0.0, -0.05);
+        // Without a minimum the retro engine would run at 0.45.
+        let linear = allocate(&LinearProgramAllocator, &engines, force, 0.0);
+        assert!(linear[2] > 0.0 && linear[2] < 0.6);
+
+        engines[2].min_throttle = 0.6;
+        let activations = allocate(&MixedIntegerAllocator::default(), &engines, force, 0.0);
+        assert!(activations[2] < 1e-6 || activations[2] >= 0.6 - 1e-6);
+    }
+
+    #[test]
+    fn respects_discrete_engines() {
+        let mut engines = ship();
+        let (force, torque) = (Vec2::new(0.05, 0.1), 0.2);
+        // The linear program throttles the RCS thrusters part way.
+        let linear = allocate(&LinearProgramAllocator, &engines, force, torque);
+        assert!(linear[3..].iter().any(|a| *a > 0.0 && *a < 1.0));
+
+        for engine in &mut engines[3..] {
+            engine.discrete = true;
+        }
+        let activations = allocate(&MixedIntegerAllocator::default(), &engines, force, torque);
+        for activation in &activations[3..] {
+            assert!(*activation < 1e-6 || *activation > 1.0 - 1e-6);
+        }
+    }
+}
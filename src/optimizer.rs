@@ -1,32 +1,34 @@
 use bevy::prelude::*;
-use minilp::{ComparisonOp, OptimizationDirection, Problem};
+use minilp::{ComparisonOp, OptimizationDirection, Problem, Solution, Variable};
+
+use crate::{
+    allocator::{self, EngineCommand},
+    AllocationMode, ResolvedEngine, SolveError, ThrusterSolverConfig,
+};
 
 pub(crate) fn estimate_acceleration(
     inverse_moment_of_inertia_sqrt: f32,
     inverse_mass: f32,
     engine_scale: f32,
     center_of_mass: Vec2,
-    engines: &[(Vec2, Vec2, f32, (Entity, usize))],
-    firing: &[f32],
+    commands: &[EngineCommand],
 ) -> (Vec2, f32) {
     let mut acceleration = Vec2::ZERO;
     let mut angular_acceleration = 0.0;
 
-    for ((engine_position, thrust_vector, max_thrust, _), firing_amount) in
-        engines.iter().zip(firing)
-    {
-        if *firing_amount > 0.0 {
-            let distance_to_com = *engine_position - center_of_mass;
+    for command in commands {
+        if command.throttle > 0.0 {
+            let distance_to_com = command.position - center_of_mass;
             //let distance_to_com = (distance_to_com * 1000.0).round() / 1000.0;
-            let thrust_vector = *thrust_vector * *max_thrust * *firing_amount * engine_scale;
+            let thrust_vector =
+                command.thrust_vector * command.max_thrust * command.throttle * engine_scale;
             //let torque = distance_to_com.x*thrust_vector.y - distance_to_com.y  * thrust_vector.x;
             //let torque = (torque * 1000.0).round() / 1000.0;
 
             let torque = distance_to_com
                 .extend(0.0)
                 .cross(thrust_vector.extend(0.0))
-                .z
-                * -1.0;
+                .z;
 
             angular_acceleration +=
                 inverse_moment_of_inertia_sqrt * (inverse_moment_of_inertia_sqrt * torque);
@@ -37,13 +39,171 @@ pub(crate) fn estimate_acceleration(
     (acceleration, angular_acceleration)
 }
 
+/// Which error terms the LP minimizes, and caps on the others carried over from an earlier
+/// stage of a prioritized solve.
+#[derive(Copy, Clone)]
+pub(crate) struct Objective {
+    torque_cost: f64,
+    force_cost: f64,
+    max_torque_error: f64,
+    max_force_error: f64,
+    /// Cost of each unit of propellant flow, on top of `fuel_consumption_weight`.
+    fuel_flow_cost: f64,
+}
+
+impl Objective {
+    pub const BLENDED: Objective = Objective {
+        torque_cost: 1.0,
+        force_cost: 1.0,
+        max_torque_error: f64::INFINITY,
+        max_force_error: f64::INFINITY,
+        fuel_flow_cost: 0.0,
+    };
+    pub const FUEL_WEIGHTED: Objective = Objective {
+        fuel_flow_cost: 1.0,
+        ..Objective::BLENDED
+    };
+    const TORQUE_ONLY: Objective = Objective {
+        force_cost: 0.0,
+        ..Objective::BLENDED
+    };
+    const FORCE_ONLY: Objective = Objective {
+        torque_cost: 0.0,
+        ..Objective::BLENDED
+    };
+}
+
+struct LpSolution {
+    activations: Vec<f32>,
+    torque_error: f64,
+    force_error: f64,
+}
+
+/// Loosens an error bound from the first stage of a prioritized solve just enough that the
+/// second stage doesn't come out infeasible because of rounding.
+fn relax(error: f64) -> f64 {
+    error.max(0.0) * (1.0 + 1e-6) + 1e-6
+}
+
 pub(crate) fn calculate_firing(
-    engines: &[(Vec2, Vec2, f32, (Entity, usize))],
+    engines: &[ResolvedEngine],
+    center_of_mass: Vec2,
+    desired_force: Vec2,
+    desired_torque: f32,
+    mode: AllocationMode,
+    config: &ThrusterSolverConfig,
+) -> Result<Vec<f32>, SolveError> {
+    let solve = |objective| {
+        solve_firing(
+            engines,
+            center_of_mass,
+            desired_force,
+            desired_torque,
+            config,
+            objective,
+        )
+    };
+    let solution = match mode {
+        AllocationMode::Blended => solve(Objective::BLENDED)?,
+        AllocationMode::TorqueFirst => {
+            let first = solve(Objective::TORQUE_ONLY)?;
+            solve(Objective {
+                max_torque_error: relax(first.torque_error),
+                ..Objective::FORCE_ONLY
+            })?
+        }
+        AllocationMode::ForceFirst => {
+            let first = solve(Objective::FORCE_ONLY)?;
+            solve(Objective {
+                max_force_error: relax(first.force_error),
+                ..Objective::TORQUE_ONLY
+            })?
+        }
+        AllocationMode::FuelOptimal => {
+            let first = solve(Objective::BLENDED)?;
+            solve(Objective {
+                torque_cost: 0.0,
+                force_cost: 0.0,
+                max_torque_error: relax(first.torque_error),
+                max_force_error: relax(first.force_error),
+                fuel_flow_cost: 1.0,
+            })?
+        }
+    };
+    Ok(solution.activations)
+}
+
+fn solve_firing(
+    engines: &[ResolvedEngine],
+    center_of_mass: Vec2,
+    desired_force: Vec2,
+    desired_torque: f32,
+    config: &ThrusterSolverConfig,
+    objective: Objective,
+) -> Result<LpSolution, SolveError> {
+    let firing_problem = build_problem(
+        engines,
+        center_of_mass,
+        desired_force,
+        desired_torque,
+        config,
+        objective,
+    );
+    let solution = firing_problem.problem.solve().map_err(solve_error)?;
+    Ok(firing_problem.extract(&solution))
+}
+
+pub(crate) fn solve_error(error: minilp::Error) -> SolveError {
+    match error {
+        minilp::Error::Infeasible => SolveError::Infeasible,
+        minilp::Error::Unbounded => SolveError::Unbounded,
+    }
+}
+
+/// The LP for a firing along with the variables needed to read a solution back out of it.
+pub(crate) struct FiringProblem {
+    pub problem: Problem,
+    pub activations: Vec<Variable>,
+    /// The activation variables representing each physical engine, in the order the engines
+    /// were given. Only gimbaled engines have more than one.
+    pub engine_activations: Vec<Vec<Variable>>,
+    u: Variable,
+    v: Variable,
+    w: Variable,
+}
+
+impl FiringProblem {
+    fn extract(&self, solution: &Solution) -> LpSolution {
+        LpSolution {
+            activations: self
+                .activations
+                .iter()
+                // FIXME: I am reducing precision here because the optimizer sometimes produces
+                // results that are _very close_ but not quite right. It's possible that some
+                // games will actualy need the extra precision and I should figure out what's
+                // wrong with the optimizer anyway
+                .map(|a| (solution[*a] as f32 * 100.0).round() / 100.0)
+                .collect(),
+            torque_error: solution[self.u],
+            force_error: solution[self.v] + solution[self.w],
+        }
+    }
+
+    pub fn activations(&self, solution: &Solution) -> Vec<f32> {
+        self.extract(solution).activations
+    }
+}
+
+pub(crate) fn build_problem(
+    engines: &[ResolvedEngine],
     center_of_mass: Vec2,
     desired_force: Vec2,
     desired_torque: f32,
-) -> Vec<f32> {
-    let total_thrust: f32 = engines.iter().map(|e| e.2).sum::<f32>();
+    config: &ThrusterSolverConfig,
+    objective: Objective,
+) -> FiringProblem {
+    let (total_thrust, total_positive_torque, total_negative_torque) =
+        allocator::thrust_totals(engines, center_of_mass);
     let mut problem = Problem::new(OptimizationDirection::Minimize);
     let mut activations = vec![];
     let mut torques = vec![];
@@ -57,40 +217,38 @@ pub(crate) fn calculate_firing(
     let mut force_y_neg_constraint = vec![];
 
     let desire_var = problem.add_var(0.0, (1.0, 1.0));
-    let u = problem.add_var(1.0, (f64::NEG_INFINITY, f64::INFINITY));
-    let v = problem.add_var(1.0, (f64::NEG_INFINITY, f64::INFINITY));
-    let w = problem.add_var(1.0, (f64::NEG_INFINITY, f64::INFINITY));
-    let mut total_positive_torque = 0.0;
-    let mut total_negative_torque = 0.0;
-
-    let torque_weight = total_thrust * 10.0;
-    let total_force_weight = 1.0;
-    let fuel_consumption_weight = 0.0001;
-    let desire = desired_force * total_thrust;
-
-    for (engine_position, thrust_vector, max_thrust, _event_key) in engines {
-        let distance_to_com = *engine_position - center_of_mass;
-        let thrust_vector = thrust_vector.normalize() * *max_thrust;
+    let u = problem.add_var(
+        objective.torque_cost,
+        (f64::NEG_INFINITY, objective.max_torque_error),
+    );
+    let v = problem.add_var(objective.force_cost, (f64::NEG_INFINITY, f64::INFINITY));
+    let w = problem.add_var(objective.force_cost, (f64::NEG_INFINITY, f64::INFINITY));
+
+    let torque_weight = total_thrust * config.torque_weight;
+    let total_force_weight = config.force_weight;
+    let fuel_consumption_weight = config.fuel_consumption_weight as f64;
+    let desire = desired_force * total_thrust * total_force_weight;
+
+    for engine in engines {
+        let distance_to_com = engine.position - center_of_mass;
+        let thrust_vector = engine.thrust_vector.normalize() * engine.max_thrust;
         let torque = distance_to_com
             .extend(0.0)
             .cross(thrust_vector.extend(0.0))
             .z
             * torque_weight;
         let ev = thrust_vector * total_force_weight;
-        let v = problem.add_var(fuel_consumption_weight, (0.0, 1.0));
+        let fuel_cost =
+            fuel_consumption_weight + objective.fuel_flow_cost * engine.fuel_consumption as f64;
+        let v = problem.add_var(fuel_cost, (0.0, 1.0));
         activations.push(v);
         torques.push(torque);
-        if torque > 0.0 {
-            total_positive_torque += torque.abs();
-        } else {
-            total_negative_torque += torque.abs();
-        }
         forces.push(ev);
     }
     let desired_torque = if desired_torque > 0.0 {
-        desired_torque * total_positive_torque
+        desired_torque * total_positive_torque * torque_weight
     } else {
-        desired_torque * total_negative_torque
+        desired_torque * total_negative_torque * torque_weight
     };
 
     for (torque, (force, v)) in torques.iter().zip(forces.iter().zip(activations.iter())) {
@@ -121,14 +279,79 @@ pub(crate) fn calculate_firing(
     force_y_neg_constraint.push((desire_var, desire.y as f64));
     problem.add_constraint(&force_y_pos_constraint, ComparisonOp::Le, 0.0);
     problem.add_constraint(&force_y_neg_constraint, ComparisonOp::Le, 0.0);
-    let solution = problem.solve().unwrap();
-
-    activations
-        .into_iter()
-        // FIXME: I am reducing precision here because the optimizer sometimes produces
-        // results that are _very close_ but not quite right. It's possible that some
-        // games will actualy need the extra precision and I should figure out what's
-        // wrong with the optimizer anyway
-        .map(|a| (solution[a] as f32 * 100.0).round() / 100.0)
-        .collect()
+    let mut engine_activations = vec![];
+    let mut remaining_activations = activations.as_slice();
+    for group in allocator::engine_groups(engines) {
+        let (group_activations, rest) = remaining_activations.split_at(group.len());
+        remaining_activations = rest;
+        // The directions representing a single gimbaled engine share its thrust.
+        if group.len() > 1 {
+            let sum: Vec<_> = group_activations.iter().map(|a| (*a, 1.0)).collect();
+            problem.add_constraint(sum.as_slice(), ComparisonOp::Le, 1.0);
+        }
+        engine_activations.push(group_activations.to_vec());
+    }
+    if objective.max_force_error.is_finite() {
+        problem.add_constraint(
+            [(v, 1.0), (w, 1.0)],
+            ComparisonOp::Le,
+            objective.max_force_error,
+        );
+    }
+    FiringProblem {
+        problem,
+        activations,
+        engine_activations,
+        u,
+        v,
+        w,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::test_support::*;
+
+    /// How far off the force and torque are when the test ship tries to push forward harder
+    /// than it can while also turning.
+    fn errors(mode: AllocationMode, config: &ThrusterSolverConfig) -> (f32, f32) {
+        let engines = ship();
+        let (force, torque) = (Vec2::new(0.0, 0.3), 0.9);
+        let activations =
+            calculate_firing(&engines, Vec2::ZERO, force, torque, mode, config).unwrap();
+        let (achieved_force, achieved_torque) = achieved(&engines, &activations);
+        let (desired_force, desired_torque) = desired(&engines, force, torque);
+        (
+            achieved_force.distance(desired_force),
+            (achieved_torque - desired_torque).abs(),
+        )
+    }
+
+    #[test]
+    fn torque_first_gives_up_force_for_torque() {
+        // With torque error this cheap, blending gives up torque to get the force right.
+        let config = ThrusterSolverConfig {
+            torque_weight: 0.1,
+            ..Default::default()
+        };
+        let (blended_force, blended_torque) = errors(AllocationMode::Blended, &config);
+        assert!(blended_force < 1e-2 && blended_torque > 0.1);
+
+        let (force, torque) = errors(AllocationMode::TorqueFirst, &config);
+        assert!(torque < 1e-2);
+        assert!(force > blended_force + 0.1);
+    }
+
+    #[test]
+    fn force_first_gives_up_torque_for_force() {
+        // With the default weights blending gives up force to get the torque right.
+        let config = ThrusterSolverConfig::default();
+        let (blended_force, blended_torque) = errors(AllocationMode::Blended, &config);
+        assert!(blended_torque < 1e-2 && blended_force > 0.1);
+
+        let (force, torque) = errors(AllocationMode::ForceFirst, &config);
+        assert!(force < 1e-2);
+        assert!(torque > blended_torque + 0.1);
+    }
 }
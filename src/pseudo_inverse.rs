@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+
+use crate::{
+    allocator, AllocationMode, ResolvedEngine, SolveError, ThrustAllocator, ThrusterSolverConfig,
+};
+
+/// A cheap allocator based on a weighted pseudo-inverse of the engines' effectiveness matrix.
+///
+/// The unconstrained minimum-effort solution is computed first. The engine which that
+/// solution drives furthest outside of `[0, 1]` is clamped and its contribution is subtracted
+/// from the desire, then the remainder is redistributed over the engines which are still
+/// free. This repeats until every engine is within its limits, which takes at most one pass
+/// per engine.
+///
+/// The result is usually close to what `LinearProgramAllocator` produces but it costs a
+/// handful of 3x3 solves rather than a full LP, which makes it a good fit for large numbers
+/// of AI ships. The solver weights control how force and torque error are traded off once
+/// engines saturate and `fuel_consumption_weight` damps the solve. In
+/// `AllocationMode::FuelOptimal` each engine's effort is weighted by its propellant flow, so
+/// thirsty engines do proportionally less of the work rather than none of it.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PseudoInverseAllocator;
+
+impl ThrustAllocator for PseudoInverseAllocator {
+    fn allocate(
+        &self,
+        engines: &[ResolvedEngine],
+        center_of_mass: Vec2,
+        desired_force: Vec2,
+        desired_torque: f32,
+        mode: AllocationMode,
+        config: &ThrusterSolverConfig,
+    ) -> Result<Vec<f32>, SolveError> {
+        let config = &mode.weight_config(config);
+        let (effects, mut remaining) = allocator::weighted_effectiveness(
+            engines,
+            center_of_mass,
+            desired_force,
+            desired_torque,
+            config,
+        );
+        let damping = (config.fuel_consumption_weight as f64).max(1e-9);
+        let costs = allocator::fuel_costs(engines, mode);
+
+        let mut gram = allocator::gram(effects.iter().zip(&costs), damping);
+
+        let mut activations = vec![0.0f64; engines.len()];
+        let mut pinned = vec![false; engines.len()];
+        for _ in 0..engines.len() {
+            let lambda = allocator::solve3(gram, remaining).ok_or(SolveError::Infeasible)?;
+
+            // Only the engine furthest outside its limits is pinned each pass. Pinning every
+            // violator at once is cheaper but often pins engines which wouldn't have saturated
+            // once the others were out of the way.
+            let mut worst: Option<(usize, f64)> = None;
+            for (i, (((e, cost), a), p)) in effects
+                .iter()
+                .zip(&costs)
+                .zip(&mut activations)
+                .zip(&pinned)
+                .enumerate()
+            {
+                if !*p {
+                    *a = (e[0] * lambda[0] + e[1] * lambda[1] + e[2] * lambda[2]) / cost;
+                    let violation = (-*a).max(*a - 1.0);
+                    if violation > worst.map_or(0.0, |(_, v)| v) {
+                        worst = Some((i, violation));
+                    }
+                }
+            }
+            let worst = match worst {
+                Some((worst, _)) => worst,
+                None => break,
+            };
+
+            // Pin the engine at its limit, take it out of the solve and hand whatever it
+            // can't deliver to the engines that are still free.
+            let a = activations[worst].clamp(0.0, 1.0);
+            activations[worst] = a;
+            pinned[worst] = true;
+            let e = &effects[worst];
+            for (r, row) in gram.iter_mut().enumerate() {
+                for (c, value) in row.iter_mut().enumerate() {
+                    *value -= e[r] * e[c] / costs[worst];
+                }
+                remaining[r] -= e[r] * a;
+            }
+        }
+
+        Ok(activations.into_iter().map(|a| a as f32).collect())
+    }
+}
@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+
+use crate::{
+    allocator, AllocationMode, ResolvedEngine, SolveError, ThrustAllocator, ThrusterSolverConfig,
+};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Bound {
+    Free,
+    Lower,
+    Upper,
+}
+
+/// An allocator which minimizes the squared, weighted error in force and torque plus a
+/// quadratic fuel penalty, with every activation kept in `[0, 1]`.
+///
+/// Because the objective is strictly convex the solution is unique and moves smoothly as the
+/// desire changes, so small changes in `desired_force` give small changes in activation
+/// rather than the on/off chattering the L1 formulation in `LinearProgramAllocator` can
+/// produce. `fuel_consumption_weight` sets the strength of the fuel penalty.
+///
+/// The problem is solved exactly with a primal active-set method. Every subproblem reduces to
+/// a 3x3 solve, so this is only slightly more expensive than `PseudoInverseAllocator`. In
+/// `AllocationMode::FuelOptimal` each engine's fuel penalty is scaled by its propellant flow,
+/// so thirsty engines do proportionally less of the work rather than none of it.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct QuadraticAllocator;
+
+impl ThrustAllocator for QuadraticAllocator {
+    fn allocate(
+        &self,
+        engines: &[ResolvedEngine],
+        center_of_mass: Vec2,
+        desired_force: Vec2,
+        desired_torque: f32,
+        mode: AllocationMode,
+        config: &ThrusterSolverConfig,
+    ) -> Result<Vec<f32>, SolveError> {
+        let config = &mode.weight_config(config);
+        let (effects, desire) = allocator::weighted_effectiveness(
+            engines,
+            center_of_mass,
+            desired_force,
+            desired_torque,
+            config,
+        );
+        let fuel_penalty = (config.fuel_consumption_weight as f64).max(1e-9);
+        let costs = allocator::fuel_costs(engines, mode);
+        let tolerance = 1e-9;
+
+        // Start with every engine off, which is always feasible, and pinned at its lower bound.
+        let mut activations = vec![0.0f64; engines.len()];
+        let mut bounds = vec![Bound::Lower; engines.len()];
+        for _ in 0..4 * engines.len() + 1 {
+            // Minimize over the free engines with the pinned ones held where they are. By the
+            // push-through identity that is a 3x3 solve however many engines are free.
+            let gram = allocator::gram(
+                effects
+                    .iter()
+                    .zip(&costs)
+                    .zip(&bounds)
+                    .filter(|(_, b)| **b == Bound::Free)
+                    .map(|(e, _)| e),
+                fuel_penalty,
+            );
+            let mut remaining = desire;
+            for ((e, a), _) in effects
+                .iter()
+                .zip(&activations)
+                .zip(&bounds)
+                .filter(|(_, b)| **b != Bound::Free)
+            {
+                for (r, value) in remaining.iter_mut().enumerate() {
+                    *value -= e[r] * a;
+                }
+            }
+            let lambda = allocator::solve3(gram, remaining).ok_or(SolveError::Infeasible)?;
+
+            // Step towards that minimum, stopping at the first bound we run into.
+            let mut step = 1.0;
+            let mut blocking = None;
+            let mut targets = vec![0.0; engines.len()];
+            for (i, (((e, cost), a), b)) in effects
+                .iter()
+                .zip(&costs)
+                .zip(&activations)
+                .zip(&bounds)
+                .enumerate()
+            {
+                if *b != Bound::Free {
+                    continue;
+                }
+                let target = (e[0] * lambda[0] + e[1] * lambda[1] + e[2] * lambda[2]) / cost;
+                targets[i] = target;
+                let (limit, bound) = if target < 0.0 {
+                    (a / (a - target), Bound::Lower)
+                } else if target > 1.0 {
+                    ((1.0 - a) / (target - a), Bound::Upper)
+                } else {
+                    continue;
+                };
+                if limit < step {
+                    step = limit;
+                    blocking = Some((i, bound));
+                }
+            }
+            for ((a, b), target) in activations.iter_mut().zip(&bounds).zip(&targets) {
+                if *b == Bound::Free {
+                    *a += step * (target - *a);
+                }
+            }
+            if let Some((i, bound)) = blocking {
+                activations[i] = if bound == Bound::Lower { 0.0 } else { 1.0 };
+                bounds[i] = bound;
+                continue;
+            }
+
+            // We are at the minimum for this set of pinned engines. If releasing one of them
+            // would lower the objective, release the one that would lower it fastest.
+            let mut residual = [-desire[0], -desire[1], -desire[2]];
+            for (e, a) in effects.iter().zip(&activations) {
+                for (r, value) in residual.iter_mut().enumerate() {
+                    *value += e[r] * a;
+                }
+            }
+            let mut release: Option<(usize, f64)> = None;
+            for (i, (((e, cost), a), b)) in effects
+                .iter()
+                .zip(&costs)
+                .zip(&activations)
+                .zip(&bounds)
+                .enumerate()
+            {
+                let gradient = e[0] * residual[0]
+                    + e[1] * residual[1]
+                    + e[2] * residual[2]
+                    + fuel_penalty * cost * a;
+                let improvement = match b {
+                    Bound::Free => continue,
+                    Bound::Lower => -gradient,
+                    Bound::Upper => gradient,
+                };
+                if improvement > release.map_or(tolerance, |(_, v)| v) {
+                    release = Some((i, improvement));
+                }
+            }
+            match release {
+                Some((i, _)) => bounds[i] = Bound::Free,
+                None => break,
+            }
+        }
+
+        Ok(activations.into_iter().map(|a| a as f32).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::test_support::*;
+
+    #[test]
+    fn small_changes_in_desire_give_small_changes_in_activation() {
+        let engines = ship();
+        let before = allocate(&QuadraticAllocator, &engines, Vec2::new(0.05, 0.2), 0.1);
+        let after = allocate(&QuadraticAllocator, &engines, Vec2::new(0.051, 0.2), 0.1);
+        for (before, after) in before.iter().zip(&after) {
+            assert!((before - after).abs() < 1e-2);
+        }
+    }
+}